@@ -27,13 +27,50 @@ impl AudioCallback<u8> for Wave {
     }
 }
 
-pub struct Audio {
+// MegaChip's digitised sound: unsigned 8-bit samples at their own rate
+struct Sample {
+    data: Vec<u8>,
+    pos: f32,
+    inc: f32,
+    looping: bool,
+}
+
+impl AudioCallback<u8> for Sample {
+    fn callback(&mut self, out: &mut [u8]) {
+        for x in out.iter_mut() {
+            if self.pos as usize >= self.data.len() {
+                if !self.looping || self.data.is_empty() {
+                    *x = 0x80;
+                    continue;
+                }
+                self.pos = 0.0;
+            }
+            *x = self.data[self.pos as usize];
+            self.pos += self.inc;
+        }
+    }
+}
+
+struct Output {
     device: AudioStreamWithCallback<Wave>,
+    sample: Option<AudioStreamWithCallback<Sample>>,
     spec: AudioSpec,
     subsystem: AudioSubsystem,
+}
+
+pub struct Audio {
+    // None when running without sound
+    output: Option<Output>,
     wave: Wave,
 }
 
+const WAVE: Wave = Wave {
+    pattern: [0x0F; 16],
+    phase: 0.0,
+    inc: 4000.0 / 32768.0,
+    volume: 255,
+};
+
 impl Audio {
     pub fn new() -> Self {
         let sdl_context = sdl3::init().unwrap();
@@ -42,42 +79,73 @@ impl Audio {
             channels: Some(1),
             format: Some(AudioFormat::U8),
         };
-        let wave = Wave {
-            pattern: [0x0F; 16],
-            phase: 0.0,
-            inc: 4000.0 / 32768.0,
-            volume: 255,
-        };
         let subsystem = sdl_context.audio().unwrap();
-        let device = subsystem.open_playback_stream(&spec, wave.clone()).unwrap();
+        let device = subsystem.open_playback_stream(&spec, WAVE).unwrap();
         Audio {
-            device,
-            spec,
-            subsystem,
-            wave,
+            output: Some(Output {
+                device,
+                sample: None,
+                spec,
+                subsystem,
+            }),
+            wave: WAVE,
+        }
+    }
+    pub fn silent() -> Self {
+        Audio {
+            output: None,
+            wave: WAVE,
         }
     }
     pub fn pause(&self) {
-        let _ = self.device.pause();
+        if let Some(output) = &self.output {
+            let _ = output.device.pause();
+        }
     }
     pub fn play(&self) {
-        let _ = self.device.resume();
+        if let Some(output) = &self.output {
+            let _ = output.device.resume();
+        }
+    }
+    // the stream gets a copy of the wave, so changes need a new stream
+    fn reopen(&mut self) {
+        if let Some(output) = &mut self.output {
+            output.device = output
+                .subsystem
+                .open_playback_stream(&output.spec, self.wave.clone())
+                .unwrap();
+        }
+    }
+    pub fn play_sample(&mut self, rate: u32, data: Vec<u8>, looping: bool) {
+        if let Some(output) = &mut self.output {
+            let sample = Sample {
+                data,
+                pos: 0.0,
+                inc: rate as f32 / 32768.0,
+                looping,
+            };
+            let stream = output
+                .subsystem
+                .open_playback_stream(&output.spec, sample)
+                .unwrap();
+            let _ = stream.resume();
+            output.sample = Some(stream);
+        }
+    }
+    pub fn stop_sample(&mut self) {
+        if let Some(output) = &mut self.output {
+            output.sample = None;
+        }
     }
     pub fn set_pattern(&mut self, pattern: &[u8; 16]) {
         self.wave.pattern = *pattern;
-        self.device = self
-            .subsystem
-            .open_playback_stream(&self.spec, self.wave.clone())
-            .unwrap();
+        self.reopen();
     }
     pub fn set_pitch(&mut self, pitch: u16) {
         // println!("Changed pitch!");
         // self.spec.freq = Some((4000.0 * 2.0_f32.powf(((pitch as f32) - 64.0) / 48.0)) as i32);
         self.wave.inc = 4000.0 * 2.0_f32.powf(((pitch as f32) - 64.0) / 48.0) / 32768.0;
         self.wave.phase = 0.0;
-        self.device = self
-            .subsystem
-            .open_playback_stream(&self.spec, self.wave.clone())
-            .unwrap();
+        self.reopen();
     }
 }
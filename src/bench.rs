@@ -0,0 +1,117 @@
+// `bench` subcommand: runs a rom headless (no window, no sound, no
+// sleeping) for a number of frames with nothing pressed, then reports how
+// many instructions per second the host managed
+//
+// `--interpret` turns off the decode cache and blocks, to compare against
+// the plain fetch, decode and execute loop
+
+use std::{env::args, fs, process::ExitCode, time::Instant};
+
+use crate::{
+    IPS,
+    audio::Audio,
+    cpu::{Cpu, Mode},
+    debug::{self, Labels},
+    display::Display,
+    parse_ips, parse_mode, pick_mode,
+    quirks::Quirks,
+    rng::{Rng, RngKind},
+    scheduler::{Scheduler, Timing},
+};
+
+const FRAMES: u64 = 6000;
+
+struct Options {
+    mode: Option<Mode>,
+    fname: String,
+    frames: u64,
+    timing: Timing,
+    interpret: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let exec_name = args().next().unwrap();
+    let usage = format!(
+        "Usage: {exec_name} bench [--frames <n>] [--ips <n> | --vip-timing] [--interpret] \
+         [<mode>] <file>"
+    );
+    let mut positional = Vec::new();
+    let mut frames = FRAMES;
+    let mut timing = Timing::Ips(IPS);
+    let mut interpret = false;
+    let mut args = args().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => {
+                let val = args.next().ok_or(&usage)?;
+                frames = val.parse().map_err(|_| format!("Invalid frames: {val}"))?;
+            }
+            "--ips" => timing = parse_ips(&args.next().ok_or(&usage)?)?,
+            "--vip-timing" => timing = Timing::Vip,
+            "--interpret" => interpret = true,
+            _ => positional.push(arg),
+        }
+    }
+    let (mode, fname) = match <[String; 2]>::try_from(positional) {
+        Ok([name, fname]) => (parse_mode(&name)?, fname),
+        Err(mut positional) if positional.len() == 1 => (None, positional.pop().unwrap()),
+        Err(_) => return Err(usage),
+    };
+    Ok(Options {
+        mode,
+        fname,
+        frames,
+        timing,
+        interpret,
+    })
+}
+
+pub fn run() -> ExitCode {
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let fname = &opts.fname;
+    let Ok(rom) = fs::read(fname) else {
+        eprintln!("Unable to open file: {fname}");
+        return ExitCode::FAILURE;
+    };
+    let mode = pick_mode(opts.mode, &rom);
+    // a fixed seed keeps runs comparable
+    let mut cpu = Cpu::with_mode(
+        mode,
+        Quirks::for_mode(mode),
+        Rng::new(RngKind::Std, 0),
+        Audio::silent(),
+    );
+    cpu.interpret = opts.interpret;
+    if let Err(e) = cpu.load(&mut rom.as_slice()) {
+        eprintln!("Unable to load {fname}: {e}");
+        return ExitCode::FAILURE;
+    }
+    let mut disp = Display::new(mode.resolution());
+    if mode == Mode::Chip8x {
+        disp.enable_color();
+    }
+    let mut sched = Scheduler::new(opts.timing);
+    let mut count = 0;
+    let start = Instant::now();
+    for _ in 0..opts.frames {
+        count += sched.run_frame(&mut cpu, &mut disp);
+        if let Some(fault) = cpu.fault {
+            eprintln!("Machine fault: {fault}");
+            eprint!("{}", debug::dump(&cpu, &Labels::empty()));
+            return ExitCode::FAILURE;
+        }
+    }
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "{count} instructions over {} frames in {secs:.3}s: {:.0} instructions per second",
+        opts.frames,
+        count as f64 / secs
+    );
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,189 @@
+// block translator
+//
+// straight-line runs of register instructions are turned into a chain of
+// closures with their operands already bound, which run in one go instead
+// of being fetched, decoded and dispatched one at a time
+//
+// a block ends at the first instruction that branches, skips, waits, draws,
+// writes memory or is otherwise unusual; that one is left to
+// `Cpu::execute`, so blocks never need to know about the display, the
+// keypad or self-modifying code
+
+use crate::{
+    cpu::{CODE_LIMIT, Cpu, Mode},
+    instruction::Instruction::{self, *},
+};
+
+pub type Op = Box<dyn Fn(&mut Cpu)>;
+
+pub struct Block {
+    pub ops: Vec<Op>,
+}
+
+const MAX_LEN: usize = 64;
+
+pub fn compile(cpu: &Cpu, start: usize) -> Block {
+    let mut ops = Vec::new();
+    let mut addr = start;
+    while ops.len() < MAX_LEN && addr + 1 < CODE_LIMIT {
+        let Some(word) = cpu.word_at(addr) else {
+            break;
+        };
+        let Some(op) = translate(Instruction::decode(word, cpu.mode), cpu.mode) else {
+            break;
+        };
+        ops.push(op);
+        addr += 2;
+    }
+    Block { ops }
+}
+
+// these must behave exactly like their arms in `Cpu::execute`; the tests
+// run every one both ways
+fn translate(ins: Instruction, mode: Mode) -> Option<Op> {
+    let op: Op = match ins {
+        SetConst(x, val) => Box::new(move |cpu| cpu.regs[x] = val),
+        AddConst(x, val) => Box::new(move |cpu| cpu.regs[x] = cpu.regs[x].wrapping_add(val)),
+        SetReg(x, y) => Box::new(move |cpu| cpu.regs[x] = cpu.regs[y]),
+        Or(x, y) => Box::new(move |cpu| {
+            cpu.regs[x] |= cpu.regs[y];
+            cpu.regs[0xF] = 0;
+        }),
+        And(x, y) => Box::new(move |cpu| {
+            cpu.regs[x] &= cpu.regs[y];
+            cpu.regs[0xF] = 0;
+        }),
+        Xor(x, y) => Box::new(move |cpu| {
+            cpu.regs[x] ^= cpu.regs[y];
+            cpu.regs[0xF] = 0;
+        }),
+        AddReg(x, y) => Box::new(move |cpu| {
+            let (sum, over) = cpu.regs[x].overflowing_add(cpu.regs[y]);
+            cpu.regs[x] = sum;
+            cpu.regs[0xF] = over as u8;
+        }),
+        SubRFromL(x, y) => Box::new(move |cpu| {
+            let (diff, under) = cpu.regs[x].overflowing_sub(cpu.regs[y]);
+            cpu.regs[x] = diff;
+            cpu.regs[0xF] = !under as u8;
+        }),
+        SubLFromR(x, y) => Box::new(move |cpu| {
+            let (diff, under) = cpu.regs[y].overflowing_sub(cpu.regs[x]);
+            cpu.regs[x] = diff;
+            cpu.regs[0xF] = !under as u8;
+        }),
+        RShift(x, y) => Box::new(move |cpu| {
+            if mode.vip_based() {
+                cpu.regs[x] = cpu.regs[y];
+            }
+            let flag = cpu.regs[x] & 1;
+            cpu.regs[x] >>= 1;
+            cpu.regs[0xF] = flag;
+        }),
+        LShift(x, y) => Box::new(move |cpu| {
+            if mode.vip_based() {
+                cpu.regs[x] = cpu.regs[y];
+            }
+            let flag = (cpu.regs[x] & 0x80 > 0) as u8;
+            cpu.regs[x] <<= 1;
+            cpu.regs[0xF] = flag;
+        }),
+        SetIndex(val) => Box::new(move |cpu| cpu.index = val as u32),
+        AddIndex(x) => Box::new(move |cpu| cpu.index = cpu.index.wrapping_add(cpu.regs[x] as u32)),
+        Font(x) => Box::new(move |cpu| cpu.index = ((cpu.regs[x] & 0xF) * 5) as u32 + 0x50),
+        Rand(x, lim) => Box::new(move |cpu| cpu.regs[x] = lim & cpu.rng.next()),
+        GetDelay(x) => Box::new(move |cpu| cpu.regs[x] = cpu.delay),
+        SetDelay(x) => Box::new(move |cpu| cpu.delay = cpu.regs[x]),
+        SetSound(x) => Box::new(move |cpu| cpu.sound = cpu.regs[x]),
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::Audio,
+        display::Display,
+        quirks::Quirks,
+        rng::{Rng, RngKind},
+    };
+
+    // every instruction a block can hold, with a few register states, run
+    // both ways
+    #[test]
+    fn matches_execute() {
+        let modes = [
+            Mode::Cosmac,
+            Mode::Eti,
+            Mode::Hires,
+            Mode::Chip10,
+            Mode::Chip8x,
+            Mode::Mega,
+            Mode::Super,
+            Mode::Xo,
+        ];
+        let states: [[u8; 16]; 3] = [
+            [0; 16],
+            [0xFF; 16],
+            std::array::from_fn(|i| (i as u8).wrapping_mul(0x37) ^ 0x81),
+        ];
+        for mode in modes {
+            let new = || {
+                Cpu::with_mode(
+                    mode,
+                    Quirks::for_mode(mode),
+                    Rng::new(RngKind::Std, 0),
+                    Audio::silent(),
+                )
+            };
+            let (mut run, mut compiled) = (new(), new());
+            let mut disp = Display::new(mode.resolution());
+            for word in (0..=0xFFFF).filter(|&word| translatable(word)) {
+                let ins = Instruction::decode(word, mode);
+                let Some(op) = translate(ins, mode) else {
+                    continue;
+                };
+                for regs in states {
+                    for cpu in [&mut run, &mut compiled] {
+                        cpu.regs = regs;
+                        cpu.index = 0xFFF0 | regs[3] as u32;
+                        cpu.delay = regs[4];
+                        cpu.sound = regs[5];
+                        cpu.rng = Rng::new(RngKind::Vip, word as u64);
+                    }
+                    run.execute(&ins, &mut disp);
+                    op(&mut compiled);
+                    let state = |cpu: &mut Cpu| {
+                        (
+                            cpu.regs,
+                            cpu.index,
+                            cpu.delay,
+                            cpu.sound,
+                            cpu.pc,
+                            cpu.rng.next(),
+                        )
+                    };
+                    assert_eq!(
+                        state(&mut run),
+                        state(&mut compiled),
+                        "{word:04x} in {} mode",
+                        mode.name()
+                    );
+                }
+            }
+        }
+    }
+
+    // the words that can decode to something a block holds; decoding
+    // anything else could panic
+    fn translatable(word: u16) -> bool {
+        match word >> 12 {
+            0x6 | 0x7 | 0xA | 0xC => true,
+            0x8 => matches!(word & 0xF, 0x0..=0x7 | 0xE),
+            0xF => matches!(word & 0xFF, 0x07 | 0x15 | 0x18 | 0x1E | 0x29),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// user configuration, an ini-style file of `key = value` lines under
+// `[section]` headers; `#` at the start of a line or after whitespace
+// starts a comment, unless it begins a value like `#ffcc00`
+//
+// a section named `[name:rom]` overrides entries of `[name]` when running
+// the rom whose file name (without extension) is `rom`, e.g.
+//
+//   [keypad]
+//   layout = qwerty
+//   [keypad:tetris]
+//   layout = numpad
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+pub const DEFAULT_PATH: &str = "chip8.cfg";
+
+pub type Section = HashMap<String, String>;
+
+pub struct Config {
+    sections: HashMap<String, Section>,
+    rom: String,
+}
+
+impl Config {
+    pub fn empty(rom: &str) -> Self {
+        Config {
+            sections: HashMap::new(),
+            rom: rom_name(rom),
+        }
+    }
+    pub fn load(path: &str, rom: &str) -> Result<Self> {
+        Config::parse(&fs::read_to_string(path)?, rom)
+    }
+    pub fn parse(text: &str, rom: &str) -> Result<Self> {
+        let mut config = Config::empty(rom);
+        let mut section = String::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_lowercase();
+            } else if let Some((key, val)) = line.split_once('=') {
+                config
+                    .sections
+                    .entry(section.clone())
+                    .or_default()
+                    .insert(key.trim().to_lowercase(), val.trim().to_string());
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: expected `key = value`", n + 1),
+                ));
+            }
+        }
+        Ok(config)
+    }
+    // entries of `[name]`, with those of `[name:rom]` taking precedence
+    pub fn section(&self, name: &str) -> Section {
+        let mut merged = self.sections.get(name).cloned().unwrap_or_default();
+        if let Some(over) = self.sections.get(&format!("{name}:{}", self.rom)) {
+            merged.extend(over.clone());
+        }
+        merged
+    }
+}
+
+// a `#` that begins a value, like `#ffcc00`, is part of it
+fn strip_comment(line: &str) -> &str {
+    let start = line
+        .char_indices()
+        .find(|&(i, c)| {
+            let before = line[..i].trim_end();
+            c == '#' && (before.is_empty() || (before.len() < i && !before.ends_with('=')))
+        })
+        .map_or(line.len(), |(i, _)| i);
+    &line[..start]
+}
+
+fn rom_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments() {
+        let config = Config::parse(
+            "# a comment\n[theme]\nforeground = #ffcc00 # yellow\nname = octo#not a comment\n",
+            "rom",
+        )
+        .unwrap();
+        let theme = config.section("theme");
+        assert_eq!(theme["foreground"], "#ffcc00");
+        assert_eq!(theme["name"], "octo#not a comment");
+    }
+}
@@ -1,70 +1,222 @@
-use std::io::{Read, Result};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Read, Result},
+    mem,
+    rc::Rc,
+};
 
 use crate::{
     audio::Audio,
-    display::Display,
+    block::{self, Block},
+    display::{Display, HI_HEIGHT, HI_WIDTH, LO_HEIGHT, LO_WIDTH},
     instruction::Instruction::{self, *},
+    keypad::Keypad,
+    quirks::Quirks,
+    rng::Rng,
+    timing,
 };
 
-const MEM_LIMIT: usize = 2_usize.pow(16);
+// memory map
+//
+//   0x000-0x1FF  the VIP's interpreter; nothing but the font is loaded
+//                here, though the VIP rng reads its own copy of
+//                0x100-0x1FF (see rng.rs)
+//     0x050-0x09F  font
+//   0x200-0xE9F  program (0x300 on CHIP-8X, 0x600 on the ETI 660, or
+//                wherever the `load_address` quirk says)
+//   0xEA0-0xECF  call stack, with the `stack_in_memory` quirk
+//   0xED0-0xFFF  unused (the VIP's interpreter variables, V registers and
+//                display buffer); programs may spill into it
+//   0x1000-      more program: XO has 64 KiB, MegaChip 16 MiB
+//
+// the others have 4 KiB; addresses past the end wrap around or fault
+// depending on the `wrap_memory` quirk
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Mode {
     Cosmac,
+    // the ETI 660, a COSMAC VIP-style interpreter that loads programs at 0x600
+    Eti,
+    // VIP interpreters patched for 64x64 and 128x64 displays
+    Hires,
+    Chip10,
+    // the VIP with the VP-590 colour board and a second keypad
+    Chip8x,
+    // SUPER-CHIP plus a 256x192 colour mode
+    Mega,
     Super,
     Xo,
 }
 
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "c" | "cosmac" => Some(Mode::Cosmac),
+            "e" | "eti" => Some(Mode::Eti),
+            "h" | "hires" => Some(Mode::Hires),
+            "10" | "chip10" => Some(Mode::Chip10),
+            "8x" | "chip8x" => Some(Mode::Chip8x),
+            "m" | "mega" => Some(Mode::Mega),
+            "s" | "super" => Some(Mode::Super),
+            "x" | "xo" => Some(Mode::Xo),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Cosmac => "cosmac",
+            Mode::Eti => "eti",
+            Mode::Hires => "hires",
+            Mode::Chip10 => "chip10",
+            Mode::Chip8x => "chip8x",
+            Mode::Mega => "mega",
+            Mode::Super => "super",
+            Mode::Xo => "xo",
+        }
+    }
+    pub fn mem_size(self) -> usize {
+        match self {
+            Mode::Xo => 0x10000,
+            Mode::Mega => 0x1000000,
+            _ => 0x1000,
+        }
+    }
+    // variants of the original COSMAC VIP interpreter
+    pub fn vip_based(self) -> bool {
+        !matches!(self, Mode::Super | Mode::Xo | Mode::Mega)
+    }
+    // the display size a program starts with
+    pub fn resolution(self) -> (usize, usize) {
+        match self {
+            Mode::Hires => (LO_WIDTH, HI_HEIGHT),
+            Mode::Chip10 => (HI_WIDTH, HI_HEIGHT),
+            _ => (LO_WIDTH, LO_HEIGHT),
+        }
+    }
+}
+
+// where the VIP interpreter keeps return addresses
+const VIP_STACK: usize = 0xEA0;
+// pc is 16 bits, so code only ever runs from the first 64 KiB
+pub const CODE_LIMIT: usize = 0x10000;
+
+// something the program did that the machine can't carry on from
+#[derive(Clone, Copy)]
+pub enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    BadAddress(usize),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::BadAddress(addr) => write!(f, "address {addr:#x} is out of range"),
+        }
+    }
+}
+
+// a subroutine call in progress
+#[derive(Clone, Copy)]
+pub struct Frame {
+    // where the call was made from
+    pub caller: u16,
+    pub target: u16,
+}
+
+// progress of an Fx0A
+enum KeyWait {
+    Idle,
+    Press,
+    Release(u8),
+}
+
 pub struct Cpu {
-    mem: [u8; MEM_LIMIT],
-    pc: u16,
-    index: u16,
-    stack: Vec<u16>,
-    delay: u8,
-    sound: u8,
+    mem: Vec<u8>,
+    // instructions already decoded, by address
+    decoded: Vec<Option<Instruction>>,
+    // compiled blocks by start address, and which bytes they were built from
+    blocks: Vec<Option<Rc<Block>>>,
+    in_block: Vec<bool>,
+    pub pc: u16,
+    // 24 bits wide for MegaChip
+    pub index: u32,
+    // MegaChip sprite size
+    sprite_size: (usize, usize),
+    pub stack: Vec<Frame>,
+    pub delay: u8,
+    pub sound: u8,
     audio: Audio,
-    mode: Mode,
-    regs: [u8; 16],
+    pub keypad: Keypad,
+    key_wait: KeyWait,
+    pub mode: Mode,
+    quirks: Quirks,
+    pub regs: [u8; 16],
+    pub rng: Rng,
+    // set when the program faults; the cpu stops with pc on the culprit
+    pub fault: Option<Fault>,
+    // the last instruction drew a sprite and has to wait for the display
+    display_wait: bool,
+    // decode every instruction afresh and never run blocks, to measure the
+    // plain interpreter
+    pub interpret: bool,
 }
 
 impl Cpu {
-    pub fn with_mode(mode: Mode) -> Self {
-        let mut mem = [0; MEM_LIMIT];
+    pub fn with_mode(mode: Mode, quirks: Quirks, rng: Rng, audio: Audio) -> Self {
+        let size = mode.mem_size();
+        let mut mem = vec![0; size];
         // load font into memory
-        mem[0x50..=0x9F].clone_from_slice(&[
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ]);
+        mem[0x50..=0x9F].clone_from_slice(&FONT);
         Cpu {
             mem,
-            pc: 0x200, // program code starts at 0x200
+            decoded: vec![None; size.min(CODE_LIMIT)],
+            blocks: vec![None; size.min(CODE_LIMIT)],
+            in_block: vec![false; size.min(CODE_LIMIT)],
+            pc: quirks.load_address,
             index: 0,
+            sprite_size: (256, 256),
             stack: Vec::new(),
             delay: 0,
             sound: 0,
-            audio: Audio::new(),
+            audio,
+            keypad: Keypad::new(),
+            key_wait: KeyWait::Idle,
             mode,
+            quirks,
             regs: [0; 16],
+            rng,
+            fault: None,
+            display_wait: false,
+            interpret: false,
         }
     }
     pub fn dec_timers(&mut self) {
         if self.delay > 0 {
             self.delay -= 1;
         }
-        if self.sound > 0 {
+        let waiting = !matches!(self.key_wait, KeyWait::Idle);
+        if self.sound > 0 && !(waiting && self.quirks.get_key_pauses_sound) {
             self.sound -= 1;
         }
         if self.sound > 0 {
@@ -76,101 +228,105 @@ impl Cpu {
     pub fn execute(&mut self, ins: &Instruction, disp: &mut Display) {
         match ins {
             ScrollUp(num) => match self.mode {
-                Mode::Cosmac => {
-                    panic!("Instruction not available in Cosmac mode. Please run in XO mode.")
-                }
-                Mode::Super => {
-                    panic!("Instruction not available in Super mode. Please run in XO mode.")
-                }
-                Mode::Xo => {
-                    for _ in 0..=*num {
-                        disp.scroll_up();
-                    }
-                }
+                Mode::Xo | Mode::Mega => disp.scroll_up(*num as usize),
+                _ => self.unavailable("XO"),
             },
             ScrollDown(num) => {
-                if let Mode::Cosmac = self.mode {
-                    panic!(
-                        "Instruction not available in Cosmac mode. Please run in Super or XO mode."
-                    )
-                }
-                for _ in 0..=*num {
-                    disp.scroll_down();
+                if self.mode.vip_based() {
+                    self.unavailable("Super or XO");
                 }
+                disp.scroll_down(*num as usize);
             }
             Clear => disp.clear(),
-            Return => self.pc = self.stack.pop().unwrap(),
+            HiresClear => match self.mode {
+                Mode::Hires => disp.clear(),
+                _ => self.unavailable("hires"),
+            },
+            Return => match self.stack.pop() {
+                None => self.raise(Fault::StackUnderflow),
+                // whatever is in memory wins, even if the program scribbled
+                // over it
+                Some(_) if self.quirks.stack_in_memory => {
+                    let at = VIP_STACK + 2 * self.stack.len();
+                    if let (Some(hi), Some(lo)) = (self.read(at), self.read(at + 1)) {
+                        self.pc = ((hi as u16) << 8) | lo as u16;
+                    }
+                }
+                Some(frame) => self.pc = frame.caller.wrapping_add(2),
+            },
             ScrollRight => disp.scroll_right(),
             ScrollLeft => disp.scroll_left(),
-            Lores => {
-                match self.mode {
-                    Mode::Cosmac => panic!(
-                        "Instruction not available in Cosmac mode. Please run in Super or XO mode."
-                    ),
-                    Mode::Super => (),
-                    Mode::Xo => disp.clear(),
+            Lores | Hires => {
+                if self.mode.vip_based() {
+                    self.unavailable("Super or XO");
                 }
-                disp.set_lores();
-            }
-            Hires => {
-                match self.mode {
-                    Mode::Cosmac => panic!(
-                        "Instruction not available in Cosmac mode. Please run in Super or XO mode."
-                    ),
-                    Mode::Super => (),
-                    Mode::Xo => disp.clear(),
+                // SUPER-CHIP's screen keeps its contents, XO-CHIP clears it
+                let keep = self.mode != Mode::Xo;
+                if !keep {
+                    disp.clear();
+                }
+                match ins {
+                    Hires => disp.set_hires(keep),
+                    _ => disp.set_lores(keep),
                 }
-                disp.set_hires();
             }
             Jump(addr) => self.pc = *addr,
             Call(addr) => {
-                self.stack.push(self.pc);
+                if self.stack.len() >= self.quirks.stack_size {
+                    self.raise(Fault::StackOverflow);
+                    return;
+                }
+                if self.quirks.stack_in_memory {
+                    let at = VIP_STACK + 2 * self.stack.len();
+                    if self.write(at, (self.pc >> 8) as u8).is_none()
+                        || self.write(at + 1, self.pc as u8).is_none()
+                    {
+                        return;
+                    }
+                }
+                self.stack.push(Frame {
+                    caller: self.pc.wrapping_sub(2),
+                    target: *addr,
+                });
                 self.pc = *addr;
             }
             SkipEqualConst(reg, val) => {
                 if self.regs[*reg] == *val {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
             SkipUnequalConst(reg, val) => {
                 if self.regs[*reg] != *val {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
             SkipEqualReg(reg1, reg2) => {
                 if self.regs[*reg1] == self.regs[*reg2] {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
             SaveRange(reg1, reg2) => match self.mode {
-                Mode::Cosmac => {
-                    panic!("Instruction not available in Cosmac mode. Please run in XO mode.")
-                }
-                Mode::Super => {
-                    panic!("Instruction not available in Super mode. Please run in XO mode.")
-                }
                 Mode::Xo => {
-                    let mut i = self.index as usize;
-                    for reg in self.regs.iter().take(*reg2 + 1).skip(*reg1) {
-                        self.mem[i] = *reg;
-                        i += 1;
+                    let regs = self.regs;
+                    let regs = regs.iter().take(*reg2 + 1).skip(*reg1);
+                    for (i, reg) in (self.index as usize..).zip(regs) {
+                        if self.write(i, *reg).is_none() {
+                            return;
+                        }
                     }
                 }
+                _ => self.unavailable("XO"),
             },
             LoadRange(reg1, reg2) => match self.mode {
-                Mode::Cosmac => {
-                    panic!("Instruction not available in Cosmac mode. Please run in XO mode.")
-                }
-                Mode::Super => {
-                    panic!("Instruction not available in Super mode. Please run in XO mode.")
-                }
                 Mode::Xo => {
-                    let mut i = self.index as usize;
-                    for reg in self.regs.iter_mut().take(*reg2 + 1).skip(*reg1) {
-                        *reg = self.mem[i];
-                        i += 1;
+                    for (i, reg) in (self.index as usize..).zip(*reg1..=*reg2) {
+                        let Some(val) = self.read(i) else {
+                            return;
+                        };
+                        self.regs[reg] = val;
                     }
                 }
+                _ => self.unavailable("XO"),
             },
             SetConst(reg, val) => self.regs[*reg] = *val,
             AddConst(reg, val) => self.regs[*reg] = self.regs[*reg].wrapping_add(*val),
@@ -203,7 +359,7 @@ impl Cpu {
             // NOTE:ambiguous instruction
             RShift(reg1, reg2) => {
                 // this is the line that's ambiguous
-                if let Mode::Cosmac = self.mode {
+                if self.mode.vip_based() {
                     self.regs[*reg1] = self.regs[*reg2];
                 }
                 let flag = self.regs[*reg1] & 1;
@@ -218,7 +374,7 @@ impl Cpu {
             // NOTE:ambiguous instruction
             LShift(reg1, reg2) => {
                 // this is the line that's ambiguous
-                if let Mode::Cosmac = self.mode {
+                if self.mode.vip_based() {
                     self.regs[*reg1] = self.regs[*reg2];
                 }
                 let flag = (self.regs[*reg1] & 0x80 > 0) as u8;
@@ -227,143 +383,603 @@ impl Cpu {
             }
             SkipUnequalReg(reg1, reg2) => {
                 if self.regs[*reg1] != self.regs[*reg2] {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
-            SetIndex(val) => self.index = *val,
+            SetIndex(val) => self.index = *val as u32,
             // TODO:ambiguous instruction; add toggle
             JumpOffset(addr) => self.pc = addr + self.regs[0] as u16,
             Rand(reg, lim) => {
-                self.regs[*reg] = lim & rand::random_range(0..=0xFF);
+                self.regs[*reg] = lim & self.rng.next();
             }
             Draw(reg_x, reg_y, height) => {
-                if !disp.just_updated {
-                    self.pc -= 2;
+                // the program's starting resolution counts as lores
+                self.display_wait =
+                    self.quirks.display_wait && disp.size() == self.mode.resolution();
+                if disp.is_mega() {
+                    let (width, height) = self.sprite_size;
+                    let Some(pixels) = self.read_range(self.index as usize, width * height) else {
+                        return;
+                    };
+                    let x = self.regs[*reg_x] as usize;
+                    let y = self.regs[*reg_y] as usize;
+                    self.regs[0xF] = disp.draw_mega(x, y, width, &pixels) as u8;
                     return;
                 }
                 self.regs[0xF] = 0;
                 let x = self.regs[*reg_x] as usize % disp.width;
                 let y = self.regs[*reg_y] as usize % disp.height;
-                if *height == 0 {
-                    match self.mode {
-                        Mode::Cosmac => return,
-                        // NOTE: Not sure if Xo mode is supposed to work
-                        // like this, but I know Octo is supposed to
-                        Mode::Super | Mode::Xo => {
-                            // draw 16x16 sprite
-                            for row in (0..32).step_by(2) {
-                                let sprite = ((self.mem[self.index as usize + row] as u16) << 8)
-                                    | self.mem[self.index as usize + row + 1] as u16;
-                                let mut mask = 0x8000;
-                                for col in 0..16 {
-                                    if sprite & mask > 0 && disp.draw_at(x + col, y + (row / 2)) {
-                                        self.regs[0xF] = 1;
-                                    }
-                                    mask >>= 1;
-                                }
-                            }
+                // NOTE: Not sure if Xo mode is supposed to draw 16x16
+                // sprites like this, but I know Octo is supposed to
+                let (rows, cols) = match (*height, self.mode) {
+                    (0, Mode::Super | Mode::Xo | Mode::Mega) => (16, 16),
+                    (0, _) => return,
+                    (height, _) => (height, 8),
+                };
+                let wrap = self.quirks.wrap_sprites;
+                let count_rows = self.quirks.collision_rows && disp.width == HI_WIDTH;
+                let mut hits = 0;
+                for row in 0..rows {
+                    let mut py = y + row;
+                    if py >= disp.height && !wrap {
+                        if count_rows {
+                            hits += 1;
                         }
+                        continue;
                     }
-                }
-                // draw normal sized 8x8 sprite
-                for row in 0..*height {
-                    let sprite = self.mem[self.index as usize + row];
-                    let mut mask = 0x80;
-                    for col in 0..8 {
-                        if sprite & mask > 0 && disp.draw_at(x + col, y + row) {
-                            self.regs[0xF] = 1;
+                    py %= disp.height;
+                    // left aligned in 16 bits, whatever the width
+                    let addr = self.index as usize + row * cols / 8;
+                    let sprite = if cols == 16 {
+                        let (Some(hi), Some(lo)) = (self.read(addr), self.read(addr + 1)) else {
+                            return;
+                        };
+                        u16::from_be_bytes([hi, lo])
+                    } else {
+                        let Some(byte) = self.read(addr) else {
+                            return;
+                        };
+                        (byte as u16) << 8
+                    };
+                    let mut hit = false;
+                    for col in 0..cols {
+                        let px = x + col;
+                        if sprite & (0x8000 >> col) == 0 || (px >= disp.width && !wrap) {
+                            continue;
                         }
-                        mask >>= 1;
+                        hit |= disp.draw_at(px % disp.width, py);
                     }
+                    hits += hit as u8;
                 }
+                self.regs[0xF] = if count_rows { hits } else { (hits > 0) as u8 };
             }
             SkipKey(reg) => {
-                if disp.key_pressed(self.regs[*reg]) {
-                    self.pc += 2;
+                if self.keypad.key_pressed(self.regs[*reg]) {
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
             SkipNotKey(reg) => {
-                if !disp.key_pressed(self.regs[*reg]) {
-                    self.pc += 2;
+                if !self.keypad.key_pressed(self.regs[*reg]) {
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
             SetIndexWide => match self.mode {
-                Mode::Cosmac => {
-                    panic!("Instruction not available in Cosmac mode. Please run in XO mode.")
-                }
-                Mode::Super => {
-                    panic!("Instruction not available in Super mode. Please run in XO mode.")
-                }
                 Mode::Xo => {
-                    self.pc += 2;
-                    self.index = ((self.mem[(self.pc - 2) as usize] as u16) << 8)
-                        | self.mem[(self.pc - 1) as usize] as u16;
-                }
+                    let addr = self.pc as usize;
+                    let (Some(hi), Some(lo)) = (self.read(addr), self.read(addr + 1)) else {
+                        return;
+                    };
+                    self.index = ((hi as u32) << 8) | lo as u32;
+                    self.pc = self.pc.wrapping_add(2);
+                }
+                _ => self.unavailable("XO"),
             },
             Audio => match self.mode {
-                Mode::Cosmac => {
-                    panic!("Instruction not available in Cosmac mode. Please run in XO mode.")
-                }
-                Mode::Super => {
-                    panic!("Instruction not available in Super mode. Please run in XO mode.")
+                Mode::Xo => {
+                    let mut pattern = [0; 16];
+                    for (i, byte) in (self.index as usize..).zip(pattern.iter_mut()) {
+                        let Some(val) = self.read(i) else {
+                            return;
+                        };
+                        *byte = val;
+                    }
+                    self.audio.set_pattern(&pattern);
                 }
-                Mode::Xo => self.audio.set_pattern(
-                    self.mem[(self.index as usize)..(self.index as usize + 16)]
-                        .try_into()
-                        .unwrap(),
-                ),
+                _ => self.unavailable("XO"),
             },
             GetDelay(reg) => self.regs[*reg] = self.delay,
             GetKey(reg) => {
-                if disp.just_pressed_key && !disp.key_pressed(self.regs[*reg]) {
-                    return;
-                } else if let Some(i) = (0..16).find(|&i| disp.key_pressed(i)) {
-                    self.regs[*reg] = i;
+                // keep re-running this instruction until a key goes down
+                // (and back up, if the platform waits for that too)
+                match self.key_wait {
+                    KeyWait::Idle | KeyWait::Press => {
+                        if let Some(key) = self.keypad.take_press() {
+                            if !self.quirks.get_key_release {
+                                self.regs[*reg] = key;
+                                self.key_wait = KeyWait::Idle;
+                                return;
+                            }
+                            self.key_wait = KeyWait::Release(key);
+                        } else {
+                            self.key_wait = KeyWait::Press;
+                        }
+                    }
+                    KeyWait::Release(key) => {
+                        if self.keypad.take_release(key) {
+                            self.regs[*reg] = key;
+                            self.key_wait = KeyWait::Idle;
+                            return;
+                        }
+                    }
                 }
-                self.pc -= 2;
+                self.pc = self.pc.wrapping_sub(2);
             }
             SetDelay(reg) => self.delay = self.regs[*reg],
             SetSound(reg) => self.sound = self.regs[*reg],
-            AddIndex(reg) => self.index = self.index.wrapping_add(self.regs[*reg] as u16),
-            Font(reg) => self.index = ((self.regs[*reg] & 0xF) * 5) as u16 + 0x50,
+            AddIndex(reg) => self.index = self.index.wrapping_add(self.regs[*reg] as u32),
+            Font(reg) => self.index = ((self.regs[*reg] & 0xF) * 5) as u32 + 0x50,
             ConvertToDecimal(reg) => {
-                self.mem[self.index as usize] = self.regs[*reg] / 100;
-                self.mem[self.index as usize + 1] = self.regs[*reg] / 10 % 10;
-                self.mem[self.index as usize + 2] = self.regs[*reg] % 10;
+                let val = self.regs[*reg];
+                let digits = [val / 100, val / 10 % 10, val % 10];
+                for (i, digit) in (self.index as usize..).zip(digits) {
+                    if self.write(i, digit).is_none() {
+                        return;
+                    }
+                }
             }
             SetPitch(pitch) => self.audio.set_pitch(*pitch),
             // NOTE:ambiguous instruction
             Store(r) => {
-                let mut i = self.index as usize;
-                for reg in self.regs.iter().take(*r + 1) {
-                    self.mem[i] = *reg;
-                    i += 1;
+                let regs = self.regs;
+                for (i, reg) in (self.index as usize..).zip(regs.iter().take(*r + 1)) {
+                    if self.write(i, *reg).is_none() {
+                        return;
+                    }
                 }
-                if let Mode::Cosmac = self.mode {
-                    self.index += *r as u16 + 1;
+                if self.mode.vip_based() {
+                    self.index += *r as u32 + 1;
                 }
             }
             // NOTE: ambiguous instruction
             Load(r) => {
-                let mut i = self.index as usize;
-                for reg in self.regs.iter_mut().take(*r + 1) {
-                    *reg = self.mem[i];
-                    i += 1;
+                for (i, reg) in (self.index as usize..).zip(0..=*r) {
+                    let Some(val) = self.read(i) else {
+                        return;
+                    };
+                    self.regs[reg] = val;
+                }
+                if self.mode.vip_based() {
+                    self.index += *r as u32 + 1;
+                }
+            }
+            CycleBackground => disp.cycle_background(),
+            // colours are 3 bits, so each nibble wraps around at 8
+            AddNibbles(reg1, reg2) => {
+                self.regs[*reg1] = ((self.regs[*reg1] & 0x77) + (self.regs[*reg2] & 0x77)) & 0x77;
+            }
+            Color(reg_x, reg_y, height) => {
+                let color = self.regs[*reg_y] & 7;
+                let horiz = self.regs[*reg_x] as usize;
+                let vert = self.regs[(*reg_x + 1) & 0xF] as usize;
+                if *height == 0 {
+                    // columns and rows of zones, as nibble pairs of
+                    // first/last; rows here are 4 pixels tall
+                    for col in (horiz & 0xF)..=(horiz >> 4) {
+                        for row in (vert & 0xF) * 4..((vert >> 4) + 1) * 4 {
+                            disp.set_color(col, row, color);
+                        }
+                    }
+                } else {
+                    for row in vert..vert + *height {
+                        disp.set_color(horiz / 8, row, color);
+                    }
                 }
-                if let Mode::Cosmac = self.mode {
-                    self.index += *r as u16 + 1;
+            }
+            SkipKey2(reg) => {
+                if self.keypad.key2_pressed(self.regs[*reg]) {
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
+            SkipNotKey2(reg) => {
+                if !self.keypad.key2_pressed(self.regs[*reg]) {
+                    self.pc = self.pc.wrapping_add(2);
+                }
+            }
+            MegaOff => disp.set_mega(false),
+            MegaOn => disp.set_mega(true),
+            SetIndexLong(hi) => {
+                let addr = self.pc as usize;
+                let (Some(mid), Some(lo)) = (self.read(addr), self.read(addr + 1)) else {
+                    return;
+                };
+                self.index = ((*hi as u32) << 16) | ((mid as u32) << 8) | lo as u32;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            // ARGB, four bytes a colour
+            LoadPalette(num) => {
+                let Some(bytes) = self.read_range(self.index as usize, *num as usize * 4) else {
+                    return;
+                };
+                let colors: Vec<u32> = bytes
+                    .chunks(4)
+                    .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                disp.set_palette(&colors);
+            }
+            // 0 stands for 256
+            SpriteWidth(num) => self.sprite_size.0 = if *num == 0 { 256 } else { *num as usize },
+            SpriteHeight(num) => self.sprite_size.1 = if *num == 0 { 256 } else { *num as usize },
+            ScreenAlpha(alpha) => disp.set_alpha(*alpha),
+            // a 16-bit sample rate and 24-bit length, then the samples
+            PlaySample(looping) => {
+                let Some(header) = self.read_range(self.index as usize, 5) else {
+                    return;
+                };
+                let rate = u16::from_be_bytes([header[0], header[1]]) as u32;
+                let len = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+                let Some(data) = self.read_range(self.index as usize + 5, len) else {
+                    return;
+                };
+                self.audio.play_sample(rate, data, *looping);
+            }
+            StopSample => self.audio.stop_sample(),
+            BlendMode(mode) => disp.set_blend(*mode),
+            CollisionColor(index) => disp.set_collision(*index),
         }
     }
-    pub fn fetch(&mut self) -> Instruction {
-        self.pc += 2;
-        Instruction::from(
-            ((self.mem[(self.pc - 2) as usize] as u16) << 8)
-                | self.mem[(self.pc - 1) as usize] as u16,
+    // whether the last instruction has to wait for the next frame before the
+    // program goes on
+    pub fn take_display_wait(&mut self) -> bool {
+        mem::take(&mut self.display_wait)
+    }
+    // programs for another platform are fatal
+    fn unavailable(&self, modes: &str) -> ! {
+        panic!(
+            "Instruction not available in {} mode. Please run in {modes} mode.",
+            self.mode.name()
         )
     }
+    // run one instruction and return what it cost on the COSMAC VIP, or
+    // None if it has to wait for a key before finishing
+    pub fn step_vip(&mut self, disp: &mut Display) -> Option<u32> {
+        let regs = self.regs;
+        let ins = self.fetch()?;
+        let next = self.pc;
+        self.execute(&ins, disp);
+        if self.pc == next.wrapping_sub(2) && matches!(ins, GetKey(_)) {
+            return None;
+        }
+        Some(timing::vip_cycles(
+            &ins,
+            &regs,
+            self.pc == next.wrapping_add(2),
+        ))
+    }
+    // run up to `max` instructions of the compiled block at pc, or just the
+    // next instruction if there's no block there; returns how many ran
+    pub fn run_block(&mut self, max: u64, disp: &mut Display) -> u64 {
+        let pc = self.pc as usize;
+        let block = match self.blocks.get(pc) {
+            _ if self.interpret => None,
+            Some(Some(block)) => Some(block.clone()),
+            Some(None) => {
+                let block = Rc::new(block::compile(self, pc));
+                self.in_block[pc..pc + 2 * block.ops.len()].fill(true);
+                self.blocks[pc] = Some(block.clone());
+                Some(block)
+            }
+            // pc is off the end of memory, which fetch deals with
+            None => None,
+        };
+        let Some(block) = block.filter(|block| !block.ops.is_empty() && max > 0) else {
+            let Some(ins) = self.fetch() else {
+                return 0;
+            };
+            self.execute(&ins, disp);
+            return 1;
+        };
+        let len = (block.ops.len() as u64).min(max);
+        for op in &block.ops[..len as usize] {
+            op(self);
+        }
+        self.pc = self.pc.wrapping_add(2 * len as u16);
+        len
+    }
+    pub fn word_at(&self, addr: usize) -> Option<u16> {
+        Some(((*self.mem.get(addr)? as u16) << 8) | *self.mem.get(addr + 1)? as u16)
+    }
+    // None if pc has run off the end of memory
+    pub fn fetch(&mut self) -> Option<Instruction> {
+        let pc = self.pc as usize;
+        self.pc = self.pc.wrapping_add(2);
+        let pc = self.resolve(pc)?;
+        // an instruction at the last byte straddles the end of memory, and
+        // only works if memory wraps
+        let next = self.resolve(pc + 1)?;
+        self.pc = (pc as u16).wrapping_add(2);
+        if let Some(ins) = self.decoded[pc].filter(|_| !self.interpret) {
+            return Some(ins);
+        }
+        let word = ((self.mem[pc] as u16) << 8) | self.mem[next] as u16;
+        let ins = Instruction::decode(word, self.mode);
+        self.decoded[pc] = Some(ins);
+        Some(ins)
+    }
     pub fn load<R: Read>(&mut self, input: &mut R) -> Result<usize> {
-        input.read(&mut self.mem[(self.pc as usize)..])
+        let mut rom = Vec::new();
+        input.read_to_end(&mut rom)?;
+        let start = self.quirks.load_address as usize;
+        let room = self.mem.len() - start;
+        if rom.len() > room {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "rom is {} bytes, but only {room} fit in this mode",
+                    rom.len()
+                ),
+            ));
+        }
+        self.mem[start..start + rom.len()].copy_from_slice(&rom);
+        // hires roms open with a jump into the interpreter patch that came
+        // with them; the program itself starts at 0x2C0
+        if self.mode == Mode::Hires && self.word_at(start) == Some(0x1260) {
+            self.pc = 0x2C0;
+        }
+        self.decoded.fill(None);
+        self.flush_blocks();
+        Ok(rom.len())
+    }
+    // where `addr` really is, or None (and a fault) if it's past the end and
+    // memory doesn't wrap
+    fn resolve(&mut self, addr: usize) -> Option<usize> {
+        if addr < self.mem.len() {
+            Some(addr)
+        } else if self.quirks.wrap_memory {
+            Some(addr % self.mem.len())
+        } else {
+            self.raise(Fault::BadAddress(addr));
+            None
+        }
+    }
+    fn read(&mut self, addr: usize) -> Option<u8> {
+        let addr = self.resolve(addr)?;
+        Some(self.mem[addr])
+    }
+    fn read_range(&mut self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        (addr..addr + len).map(|i| self.read(i)).collect()
+    }
+    // every write to memory goes through here, so that self-modifying code
+    // doesn't run stale instructions out of the cache
+    fn write(&mut self, addr: usize, val: u8) -> Option<()> {
+        let addr = self.resolve(addr)?;
+        self.mem[addr] = val;
+        if addr >= CODE_LIMIT {
+            return Some(());
+        }
+        // the byte is the first or second half of an instruction, and the
+        // second half of the one at the last byte is the first byte
+        self.decoded[addr] = None;
+        let prev = addr.checked_sub(1).unwrap_or(self.mem.len() - 1);
+        if prev < CODE_LIMIT {
+            self.decoded[prev] = None;
+        }
+        // rare enough that throwing every block away is fine
+        if self.in_block[addr] {
+            self.flush_blocks();
+        }
+        Some(())
+    }
+    // stop with pc back on the instruction that faulted
+    fn raise(&mut self, fault: Fault) {
+        if self.fault.is_none() {
+            self.pc = self.pc.wrapping_sub(2);
+            self.fault = Some(fault);
+        }
+    }
+    fn flush_blocks(&mut self) {
+        self.blocks.fill(None);
+        self.in_block.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::RngKind;
+
+    fn machine(mode: Mode, hires: bool) -> (Cpu, Display) {
+        let cpu = Cpu::with_mode(
+            mode,
+            Quirks::for_mode(mode),
+            Rng::new(RngKind::Std, 0),
+            Audio::silent(),
+        );
+        let mut disp = Display::new(mode.resolution());
+        if hires {
+            disp.set_hires(false);
+        }
+        (cpu, disp)
+    }
+
+    // draws `sprite` at (x, y) and returns VF
+    fn draw(
+        cpu: &mut Cpu,
+        disp: &mut Display,
+        sprite: &[u8],
+        (x, y): (u8, u8),
+        height: usize,
+    ) -> u8 {
+        cpu.mem[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+        cpu.index = 0x300;
+        cpu.regs[0] = x;
+        cpu.regs[1] = y;
+        cpu.execute(&Draw(0, 1, height), disp);
+        cpu.regs[0xF]
+    }
+
+    fn lit(disp: &Display, x: usize, y: usize) -> bool {
+        disp.buffer()[x + y * disp.width] != 0
+    }
+
+    #[test]
+    fn right_edge() {
+        let (mut cpu, mut disp) = machine(Mode::Xo, false);
+        draw(&mut cpu, &mut disp, &[0xFF], (60, 0), 1);
+        assert!(lit(&disp, 63, 0));
+        assert!(lit(&disp, 0, 0) && lit(&disp, 3, 0));
+        assert!(!lit(&disp, 4, 0));
+
+        let (mut cpu, mut disp) = machine(Mode::Cosmac, false);
+        draw(&mut cpu, &mut disp, &[0xFF], (60, 0), 1);
+        assert!(lit(&disp, 63, 0));
+        assert!(!lit(&disp, 0, 0));
+    }
+
+    #[test]
+    fn bottom_edge() {
+        let (mut cpu, mut disp) = machine(Mode::Xo, false);
+        draw(&mut cpu, &mut disp, &[0x80, 0x80], (0, 31), 2);
+        assert!(lit(&disp, 0, 31) && lit(&disp, 0, 0));
+
+        let (mut cpu, mut disp) = machine(Mode::Cosmac, false);
+        draw(&mut cpu, &mut disp, &[0x80, 0x80], (0, 31), 2);
+        assert!(lit(&disp, 0, 31));
+        assert!(!lit(&disp, 0, 0));
+    }
+
+    #[test]
+    fn start_position_wraps() {
+        let (mut cpu, mut disp) = machine(Mode::Cosmac, false);
+        draw(&mut cpu, &mut disp, &[0x80], (64 + 5, 32 + 2), 1);
+        assert!(lit(&disp, 5, 2));
+    }
+
+    #[test]
+    fn super_hires_counts_rows() {
+        let (mut cpu, mut disp) = machine(Mode::Super, true);
+        let sprite = [0x80, 0x80, 0x80];
+        // one row falls off the bottom
+        assert_eq!(draw(&mut cpu, &mut disp, &sprite, (0, 62), 3), 1);
+        // and now the other two collide
+        assert_eq!(draw(&mut cpu, &mut disp, &sprite, (0, 62), 3), 3);
+        // a row that collides in more than one pixel still counts once
+        let (mut cpu, mut disp) = machine(Mode::Super, true);
+        draw(&mut cpu, &mut disp, &[0xFF, 0xFF], (0, 0), 2);
+        assert_eq!(draw(&mut cpu, &mut disp, &[0xFF, 0xFF], (0, 0), 2), 2);
+    }
+
+    #[test]
+    fn flag_is_one_elsewhere() {
+        let sprite = [0x80, 0x80, 0x80];
+        for (mode, hires) in [
+            (Mode::Super, false),
+            (Mode::Cosmac, false),
+            (Mode::Xo, true),
+            (Mode::Chip10, false),
+        ] {
+            let (mut cpu, mut disp) = machine(mode, hires);
+            let y = disp.height as u8 - 2;
+            assert_eq!(draw(&mut cpu, &mut disp, &sprite, (0, y), 3), 0);
+            assert_eq!(draw(&mut cpu, &mut disp, &sprite, (0, y), 3), 1);
+        }
+    }
+
+    #[test]
+    fn big_sprite_clips() {
+        let (mut cpu, mut disp) = machine(Mode::Super, true);
+        let sprite = [0xFF; 32];
+        // half of it hangs off the right, half off the bottom
+        assert_eq!(draw(&mut cpu, &mut disp, &sprite, (120, 56), 0), 8);
+        assert!(lit(&disp, 127, 63));
+        assert!(!lit(&disp, 0, 56) && !lit(&disp, 120, 0));
+        assert_eq!(draw(&mut cpu, &mut disp, &sprite, (120, 56), 0), 16);
+    }
+
+    #[test]
+    fn big_sprite_wraps() {
+        let (mut cpu, mut disp) = machine(Mode::Xo, false);
+        let sprite = [0xFF; 32];
+        assert_eq!(draw(&mut cpu, &mut disp, &sprite, (56, 24), 0), 0);
+        assert!(lit(&disp, 63, 31));
+        assert!(lit(&disp, 0, 0) && lit(&disp, 7, 7));
+        assert!(!lit(&disp, 8, 8));
+        assert_eq!(draw(&mut cpu, &mut disp, &sprite, (56, 24), 0), 1);
+    }
+
+    #[test]
+    fn long_block_runs_in_part() {
+        let (mut cpu, mut disp) = machine(Mode::Cosmac, false);
+        // twenty `add v0, 1`, more than a frame's worth at the default speed,
+        // then a loop
+        let mut rom = [0x70, 0x01].repeat(20);
+        rom.extend([0x12, 0x28]);
+        cpu.load(&mut rom.as_slice()).unwrap();
+        assert_eq!(cpu.run_block(12, &mut disp), 12);
+        assert_eq!((cpu.pc, cpu.regs[0]), (0x200 + 24, 12));
+        assert_eq!(cpu.run_block(12, &mut disp), 8);
+        assert_eq!((cpu.pc, cpu.regs[0]), (0x200 + 40, 20));
+    }
+
+    #[test]
+    fn block_at_end_of_memory() {
+        let (mut cpu, mut disp) = machine(Mode::Xo, false);
+        cpu.mem[0xFFFE..].copy_from_slice(&[0x70, 0x01]);
+        cpu.pc = 0xFFFE;
+        assert_eq!(cpu.run_block(12, &mut disp), 1);
+        assert_eq!((cpu.pc, cpu.regs[0]), (0, 1));
+    }
+
+    #[test]
+    fn instruction_across_end_of_memory() {
+        // `add v0, 1` split between the last byte and the first
+        let (mut cpu, mut disp) = machine(Mode::Cosmac, false);
+        cpu.mem[0xFFF] = 0x70;
+        cpu.mem[0] = 0x01;
+        cpu.pc = 0xFFF;
+        let ins = cpu.fetch().unwrap();
+        cpu.execute(&ins, &mut disp);
+        assert_eq!(cpu.regs[0], 1);
+        assert!(cpu.fault.is_none());
+        // and rewriting the first byte is seen
+        cpu.write(0, 0x05).unwrap();
+        cpu.pc = 0xFFF;
+        let ins = cpu.fetch().unwrap();
+        cpu.execute(&ins, &mut disp);
+        assert_eq!(cpu.regs[0], 6);
+
+        // without wrapping it's a fault
+        let (mut cpu, _) = machine(Mode::Super, false);
+        cpu.pc = 0xFFF;
+        assert!(cpu.fetch().is_none());
+        assert!(matches!(cpu.fault, Some(Fault::BadAddress(0x1000))));
+        assert_eq!(cpu.pc, 0xFFF);
+    }
+
+    #[test]
+    fn top_of_memory() {
+        let (mut cpu, mut disp) = machine(Mode::Xo, false);
+        let mut step = |cpu: &mut Cpu| {
+            let ins = cpu.fetch().unwrap();
+            cpu.execute(&ins, &mut disp);
+        };
+        // a call from the last instruction returns to the first
+        cpu.mem[0xFFFE..].copy_from_slice(&[0x23, 0x00]);
+        cpu.mem[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+        cpu.pc = 0xFFFE;
+        step(&mut cpu);
+        assert_eq!((cpu.pc, cpu.stack[0].caller), (0x300, 0xFFFE));
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0);
+        // and so does skipping over it
+        cpu.mem[0xFFFC..0xFFFE].copy_from_slice(&[0x30, 0x00]);
+        cpu.pc = 0xFFFC;
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0);
+        // waiting for a key there stays put
+        cpu.write(0xFFFE, 0xF0).unwrap();
+        cpu.write(0xFFFF, 0x0A).unwrap();
+        cpu.pc = 0xFFFE;
+        step(&mut cpu);
+        assert_eq!(cpu.pc, 0xFFFE);
     }
 }
@@ -0,0 +1,68 @@
+// state dumps for when something goes wrong
+//
+// subroutines are named from the `[labels]` section, e.g.
+//
+//   [labels]
+//   draw_score = 0x2A4
+//
+// and otherwise get a name made from their address, like `sub_2a4`
+
+use std::collections::HashMap;
+
+use crate::{config::Section, cpu::Cpu};
+
+pub struct Labels {
+    names: HashMap<u16, String>,
+}
+
+impl Labels {
+    pub fn empty() -> Self {
+        Labels {
+            names: HashMap::new(),
+        }
+    }
+    pub fn from_config(section: &Section) -> Result<Self, String> {
+        let mut names = HashMap::new();
+        for (name, val) in section {
+            let addr = val
+                .strip_prefix("0x")
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .ok_or(format!("Invalid address for label {name}: {val}"))?;
+            names.insert(addr, name.clone());
+        }
+        Ok(Labels { names })
+    }
+    pub fn name(&self, addr: u16) -> String {
+        match self.names.get(&addr) {
+            Some(name) => name.clone(),
+            None => format!("sub_{addr:03x}"),
+        }
+    }
+}
+
+// innermost call first
+pub fn call_stack(cpu: &Cpu, labels: &Labels) -> String {
+    if cpu.stack.is_empty() {
+        return "  (no calls in progress)\n".to_string();
+    }
+    let mut out = String::new();
+    for (depth, frame) in cpu.stack.iter().enumerate().rev() {
+        out += &format!(
+            "  #{depth} {} (called from {:#05x})\n",
+            labels.name(frame.target),
+            frame.caller
+        );
+    }
+    out
+}
+
+pub fn dump(cpu: &Cpu, labels: &Labels) -> String {
+    let mut out = format!("pc {:#05x}  i {:#05x}\n", cpu.pc, cpu.index);
+    for (n, reg) in cpu.regs.iter().enumerate() {
+        out += &format!("v{n:x} {reg:02x}");
+        out += if n % 8 == 7 { "\n" } else { "  " };
+    }
+    out += "call stack:\n";
+    out += &call_stack(cpu, labels);
+    out
+}
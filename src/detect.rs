@@ -0,0 +1,121 @@
+// guessing which platform a rom was written for
+//
+// roms don't say, so this looks for instructions that only some platforms
+// have among the code it can find; it's still a guess, and picking a mode
+// explicitly always wins
+
+use crate::cpu::Mode;
+
+pub fn detect(rom: &[u8]) -> Mode {
+    // hires roms all open with the same jump
+    if rom.starts_with(&[0x12, 0x60]) {
+        return Mode::Hires;
+    }
+    let words = trace(rom);
+    if words.contains(&0x0011) {
+        return Mode::Mega;
+    }
+    if words.iter().any(|&word| xo_only(word)) {
+        return Mode::Xo;
+    }
+    if words.iter().any(|&word| super_only(word)) {
+        return Mode::Super;
+    }
+    Mode::Cosmac
+}
+
+// the instructions that can be reached from the start by following jumps,
+// calls and skips, so that sprites and other data aren't mistaken for code
+//
+// jumps through Bnnn can't be followed, so whatever is only reached that
+// way doesn't count
+fn trace(rom: &[u8]) -> Vec<u16> {
+    let word_at = |at: usize| Some(u16::from_be_bytes([*rom.get(at)?, *rom.get(at + 1)?]));
+    // XO-CHIP's long index load and MegaChip's take up four bytes
+    let len = |word: u16| {
+        if matches!(word, 0xF000 | 0x0100..=0x01FF) {
+            4
+        } else {
+            2
+        }
+    };
+    let mut seen = vec![false; rom.len()];
+    let mut words = Vec::new();
+    let mut todo = vec![0];
+    while let Some(at) = todo.pop() {
+        let Some(word) = word_at(at) else {
+            continue;
+        };
+        if seen[at] {
+            continue;
+        }
+        seen[at] = true;
+        words.push(word);
+        let next = at + len(word);
+        // roms are loaded at 0x200
+        let target = ((word & 0xFFF) as usize).checked_sub(0x200);
+        match word >> 12 {
+            0x0 if matches!(word, 0x00EE | 0x00FD) => {}
+            0x1 => todo.extend(target),
+            0x2 => todo.extend(target.into_iter().chain([next])),
+            0x3 | 0x4 | 0x9 | 0xE => {
+                todo.push(next);
+                todo.push(next + word_at(next).map_or(2, len));
+            }
+            0x5 if word & 0xF == 0 => {
+                todo.push(next);
+                todo.push(next + word_at(next).map_or(2, len));
+            }
+            0xB => {}
+            _ => todo.push(next),
+        }
+    }
+    words
+}
+
+fn xo_only(word: u16) -> bool {
+    matches!(word, 0xF000 | 0xF002 | 0x00D1..=0x00DF)
+        || matches!(word & 0xF00F, 0x5002 | 0x5003)
+        || matches!(word & 0xF0FF, 0xF001 | 0xF03A)
+}
+
+fn super_only(word: u16) -> bool {
+    matches!(word, 0x00FB..=0x00FF | 0x00C1..=0x00CF)
+        || matches!(word & 0xF0FF, 0xF030 | 0xF075 | 0xF085)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_isnt_code() {
+        // draws a sprite whose rows happen to read as F000, 0011 and 00FF,
+        // then loops forever
+        let rom = [
+            0xA2, 0x08, 0xD0, 0x16, 0x12, 0x04, 0x00, 0x00, //
+            0xF0, 0x00, 0x00, 0x11, 0x00, 0xFF,
+        ];
+        assert!(rom.chunks_exact(2).any(|pair| pair == [0xF0, 0x00]));
+        assert_eq!(detect(&rom), Mode::Cosmac);
+    }
+
+    #[test]
+    fn platforms() {
+        assert_eq!(detect(&[0x12, 0x60, 0x00, 0xE0]), Mode::Hires);
+        assert_eq!(detect(&[0x00, 0x11, 0x12, 0x02]), Mode::Mega);
+        // a long index load, with the address after it skipped over
+        assert_eq!(detect(&[0xF0, 0x00, 0x00, 0x11, 0x12, 0x04]), Mode::Xo);
+        assert_eq!(detect(&[0x00, 0xFF, 0x00, 0xE0, 0x12, 0x04]), Mode::Super);
+    }
+
+    #[test]
+    fn follows_calls_and_skips() {
+        // a call to a subroutine that switches to hires, after a skip
+        let rom = [
+            0x30, 0x01, 0x22, 0x06, 0x12, 0x04, // main
+            0x00, 0xFF, 0x00, 0xEE, // subroutine
+        ];
+        assert_eq!(detect(&rom), Mode::Super);
+    }
+}
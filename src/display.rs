@@ -1,145 +1,315 @@
-// display (monochrome, 64x32 or 128x64 for SUPER-CHIP)
+// display (monochrome, 64x32, 64x64 for hires CHIP-8, or 128x64 for
+// SUPER-CHIP and CHIP-10)
+//
+// the buffer holds one entry per pixel at the current resolution, and is
+// only scaled up when it's shown
+//
+// CHIP-8X adds a colour layer on top: one foreground colour per zone of
+// 8x1 pixels and a single background colour
+//
+// MegaChip mode swaps the buffer for a 256x192 one drawn in colours from a
+// 256-entry palette, with sprites blended into what's already there
 
-use minifb::{Key::*, Window, WindowOptions};
+use std::borrow::Cow;
 
-const SCALE: usize = 8;
 pub const LO_WIDTH: usize = 64;
 pub const LO_HEIGHT: usize = 32;
 pub const HI_WIDTH: usize = 128;
 pub const HI_HEIGHT: usize = 64;
+pub const MEGA_WIDTH: usize = 256;
+pub const MEGA_HEIGHT: usize = 192;
 const OFF: u32 = 0;
 const ON: u32 = u32::MAX;
+// the VP-590's colours
+const FOREGROUND: [u32; 8] = [
+    0x000000, 0xFF0000, 0x0000FF, 0xFF00FF, 0x00FF00, 0xFFFF00, 0x00FFFF, 0xFFFFFF,
+];
+const BACKGROUND: [u32; 4] = [0x000080, 0x000000, 0x008000, 0x800000];
+const ZONE_COLS: usize = LO_WIDTH / 8;
+
+struct Colors {
+    zones: [u8; ZONE_COLS * LO_HEIGHT],
+    background: usize,
+}
+
+// how MegaChip sprites combine with the screen
+#[derive(Clone, Copy)]
+enum Blend {
+    Normal,
+    Quarter,
+    Half,
+    Add,
+    Multiply,
+}
+
+struct Mega {
+    palette: [u32; 256],
+    // palette index of every pixel, for collisions
+    indices: Vec<u8>,
+    blend: Blend,
+    // fades the whole screen
+    alpha: u8,
+    // drawing over this palette index counts as a collision
+    collision: u8,
+}
 
 pub struct Display {
     pub width: usize,
     pub height: usize,
-    buffer: [u32; HI_WIDTH * HI_HEIGHT],
-    pub window: Window,
-    hires: bool,
-    pub just_updated: bool,
-    pub just_pressed_key: bool,
+    buffer: Vec<u32>,
+    colors: Option<Colors>,
+    mega: Option<Mega>,
 }
 
 impl Display {
-    pub fn with_fps(fps: usize) -> Display {
-        let mut disp = Display {
-            width: LO_WIDTH,
-            height: LO_HEIGHT,
-            buffer: [OFF; HI_WIDTH * HI_HEIGHT],
-            window: Window::new(
-                "Chip-8",
-                HI_WIDTH * SCALE,
-                HI_HEIGHT * SCALE,
-                WindowOptions::default(),
-            )
-            .unwrap_or_else(|e| {
-                panic!("{e}");
-            }),
-            hires: false,
-            just_updated: true,
-            just_pressed_key: false,
+    pub fn new((width, height): (usize, usize)) -> Display {
+        Display {
+            width,
+            height,
+            buffer: vec![OFF; width * height],
+            colors: None,
+            mega: None,
+        }
+    }
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+    pub fn enable_color(&mut self) {
+        // the interpreter starts with every zone red
+        self.colors = Some(Colors {
+            zones: [1; ZONE_COLS * LO_HEIGHT],
+            background: 0,
+        });
+    }
+    pub fn buffer(&self) -> Cow<'_, [u32]> {
+        if let Some(mega) = &self.mega
+            && mega.alpha < u8::MAX
+        {
+            let fade =
+                |c: u32, shift: u32| ((c >> shift & 0xFF) * mega.alpha as u32 / 255) << shift;
+            let frame = self
+                .buffer
+                .iter()
+                .map(|&c| fade(c, 16) | fade(c, 8) | fade(c, 0));
+            return Cow::Owned(frame.collect());
+        }
+        let Some(colors) = &self.colors else {
+            return Cow::Borrowed(&self.buffer);
+        };
+        let mut frame = self.buffer.clone();
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            let (x, y) = (i % self.width, i / self.width);
+            *pixel = if *pixel == ON {
+                FOREGROUND[colors.zones[x / 8 + y * ZONE_COLS] as usize]
+            } else {
+                BACKGROUND[colors.background]
+            };
+        }
+        Cow::Owned(frame)
+    }
+    pub fn cycle_background(&mut self) {
+        if let Some(colors) = &mut self.colors {
+            colors.background = (colors.background + 1) % BACKGROUND.len();
+        }
+    }
+    // zones off the edge wrap around, like sprites do
+    pub fn set_color(&mut self, col: usize, row: usize, color: u8) {
+        if let Some(colors) = &mut self.colors {
+            colors.zones[col % ZONE_COLS + row % LO_HEIGHT * ZONE_COLS] = color;
+        }
+    }
+    pub fn is_monochrome(&self) -> bool {
+        self.colors.is_none() && self.mega.is_none()
+    }
+    pub fn is_mega(&self) -> bool {
+        self.mega.is_some()
+    }
+    pub fn set_mega(&mut self, on: bool) {
+        if on == self.is_mega() {
+            return;
+        }
+        self.mega = on.then(|| Mega {
+            palette: [OFF; 256],
+            indices: Vec::new(),
+            blend: Blend::Normal,
+            alpha: u8::MAX,
+            collision: 0,
+        });
+        if on {
+            self.set_resolution(MEGA_WIDTH, MEGA_HEIGHT, false);
+        } else {
+            self.set_resolution(LO_WIDTH, LO_HEIGHT, false);
+        }
+    }
+    // `colors` are ARGB; palette entry 0 is always transparent
+    pub fn set_palette(&mut self, colors: &[u32]) {
+        if let Some(mega) = &mut self.mega {
+            for (entry, color) in mega.palette[1..].iter_mut().zip(colors) {
+                *entry = color & 0xFFFFFF;
+            }
+        }
+    }
+    pub fn set_blend(&mut self, mode: u8) {
+        if let Some(mega) = &mut self.mega {
+            mega.blend = match mode {
+                1 => Blend::Quarter,
+                2 => Blend::Half,
+                3 => Blend::Add,
+                4 => Blend::Multiply,
+                _ => Blend::Normal,
+            };
+        }
+    }
+    pub fn set_alpha(&mut self, alpha: u8) {
+        if let Some(mega) = &mut self.mega {
+            mega.alpha = alpha;
+        }
+    }
+    pub fn set_collision(&mut self, index: u8) {
+        if let Some(mega) = &mut self.mega {
+            mega.collision = index;
+        }
+    }
+    // draw a sprite of palette indices, clipped at the edges; returns
+    // whether it hit the collision colour
+    pub fn draw_mega(&mut self, x: usize, y: usize, width: usize, pixels: &[u8]) -> bool {
+        let Some(mega) = &mut self.mega else {
+            return false;
         };
-        disp.window.set_target_fps(fps);
-        disp
+        let mut hit = false;
+        for (n, &index) in pixels.iter().enumerate() {
+            let (px, py) = (x + n % width, y + n / width);
+            if index == 0 || px >= self.width || py >= self.height {
+                continue;
+            }
+            let i = px + py * self.width;
+            if mega.indices[i] == mega.collision {
+                hit = true;
+            }
+            mega.indices[i] = index;
+            self.buffer[i] = blend(mega.blend, mega.palette[index as usize], self.buffer[i]);
+        }
+        hit
     }
     pub fn clear(&mut self) {
         self.buffer.fill(OFF);
+        if let Some(mega) = &mut self.mega {
+            mega.indices.fill(0);
+        }
     }
-    // NOTE: this may or may not work
     pub fn draw_at(&mut self, x: usize, y: usize) -> bool {
         if x >= self.width || y >= self.height {
             return false;
         }
-        let mut i = x + y * HI_WIDTH;
-        if self.hires {
-            self.buffer[i] = if self.buffer[i] == ON { OFF } else { ON };
-        } else {
-            i *= 2;
-            self.buffer[i] = if self.buffer[i] == ON { OFF } else { ON };
-            self.buffer[i + 1] = if self.buffer[i + 1] == ON { OFF } else { ON };
-            self.buffer[i + HI_WIDTH] = if self.buffer[i + HI_WIDTH] == ON {
-                OFF
-            } else {
-                ON
-            };
-            self.buffer[i + HI_WIDTH + 1] = if self.buffer[i + HI_WIDTH + 1] == ON {
-                OFF
-            } else {
-                ON
-            };
-        }
+        let i = x + y * self.width;
+        self.buffer[i] = if self.buffer[i] == ON { OFF } else { ON };
         // did we "turn off" the pixel?
         // e.g. was there a collision?
         self.buffer[i] == OFF
     }
-    pub fn key_pressed(&mut self, key: u8) -> bool {
-        self.just_pressed_key = self.window.is_key_down(match key {
-            0x1 => Key1,
-            0x2 => Key2,
-            0x3 => Key3,
-            0xC => Key4,
-            0x4 => Q,
-            0x5 => W,
-            0x6 => E,
-            0xD => R,
-            0x7 => A,
-            0x8 => S,
-            0x9 => D,
-            0xE => F,
-            0xA => Z,
-            0x0 => X,
-            0xB => C,
-            0xF => V,
-            _ => panic!(),
-        });
-        self.just_pressed_key
+    // FNV-1a over the size and the framebuffer, used to check that replays
+    // match
+    pub fn hash(&self) -> u64 {
+        let size = [self.width as u32, self.height as u32];
+        size.iter()
+            .chain(self.buffer().iter())
+            .fold(0xCBF29CE484222325, |hash, &word| {
+                (hash ^ word as u64).wrapping_mul(0x100000001B3)
+            })
     }
-    pub fn scroll_down(&mut self) {
-        self.buffer.rotate_right(HI_WIDTH);
-        for i in 0..HI_WIDTH {
-            self.buffer[i] = OFF;
-        }
+    // scrolling is in pixels at the current resolution
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.shift(0, rows as isize);
     }
     pub fn scroll_left(&mut self) {
-        for y in 0..HI_HEIGHT {
-            for x in 0..(HI_WIDTH - 4) {
-                self.buffer[x + y * HI_WIDTH] = self.buffer[x + y * HI_WIDTH + 4]
-            }
-            for x in (HI_WIDTH - 4)..HI_WIDTH {
-                self.buffer[x + y * HI_WIDTH] = OFF;
-            }
-        }
+        self.shift(-4, 0);
     }
     pub fn scroll_right(&mut self) {
-        for y in 0..HI_HEIGHT {
-            for x in (4..HI_WIDTH).rev() {
-                self.buffer[x + y * HI_WIDTH] = self.buffer[x + y * HI_WIDTH - 4]
-            }
-            for x in 0..4 {
-                self.buffer[x + y * HI_WIDTH] = OFF;
-            }
-        }
+        self.shift(4, 0);
+    }
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.shift(0, -(rows as isize));
     }
-    pub fn scroll_up(&mut self) {
-        self.buffer.rotate_left(HI_WIDTH);
-        for i in (HI_WIDTH * (HI_HEIGHT - 1))..(HI_WIDTH * HI_HEIGHT) {
-            self.buffer[i] = OFF;
+    fn shift(&mut self, dx: isize, dy: isize) {
+        shift(&mut self.buffer, self.width, dx, dy, OFF);
+        if let Some(mega) = &mut self.mega {
+            shift(&mut mega.indices, self.width, dx, dy, 0);
         }
     }
-    pub fn set_hires(&mut self) {
-        self.hires = true;
-        self.width = HI_WIDTH;
-        self.height = HI_HEIGHT;
+    // `keep` scales what's on screen to the new resolution, otherwise it's
+    // cleared
+    pub fn set_hires(&mut self, keep: bool) {
+        self.set_resolution(HI_WIDTH, HI_HEIGHT, keep);
+    }
+    pub fn set_lores(&mut self, keep: bool) {
+        self.set_resolution(LO_WIDTH, LO_HEIGHT, keep);
     }
-    pub fn set_lores(&mut self) {
-        self.hires = false;
-        self.width = LO_WIDTH;
-        self.height = LO_HEIGHT;
+    fn set_resolution(&mut self, width: usize, height: usize, keep: bool) {
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        let mut buffer = vec![OFF; width * height];
+        if keep {
+            // going down a resolution keeps the top left of each block
+            for (i, pixel) in buffer.iter_mut().enumerate() {
+                let (x, y) = (
+                    i % width * self.width / width,
+                    i / width * self.height / height,
+                );
+                *pixel = self.buffer[x + y * self.width];
+            }
+        }
+        self.buffer = buffer;
+        (self.width, self.height) = (width, height);
+        if let Some(mega) = &mut self.mega {
+            mega.indices = vec![0; width * height];
+        }
+    }
+}
+
+// move everything in a `cols` wide buffer by (dx, dy), filling the gap
+fn shift<T: Copy>(buf: &mut [T], cols: usize, dx: isize, dy: isize, fill: T) {
+    let old = buf.to_vec();
+    for (i, pixel) in buf.iter_mut().enumerate() {
+        let x = (i % cols) as isize - dx;
+        let y = (i / cols) as isize - dy;
+        let src = x + y * cols as isize;
+        *pixel = if (0..cols as isize).contains(&x) && (0..old.len() as isize).contains(&src) {
+            old[src as usize]
+        } else {
+            fill
+        };
     }
-    pub fn update(&mut self) {
-        self.window
-            .update_with_buffer(&self.buffer, HI_WIDTH, HI_HEIGHT)
-            .unwrap();
-        self.just_updated = true;
+}
+
+fn blend(mode: Blend, src: u32, dst: u32) -> u32 {
+    let channel = |shift: u32| {
+        let (s, d) = (src >> shift & 0xFF, dst >> shift & 0xFF);
+        let mixed = match mode {
+            Blend::Normal => s,
+            Blend::Quarter => (s + 3 * d) / 4,
+            Blend::Half => (s + d) / 2,
+            Blend::Add => (s + d).min(0xFF),
+            Blend::Multiply => s * d / 0xFF,
+        };
+        mixed << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_covers_size() {
+        let blank = Display::new((LO_WIDTH, LO_HEIGHT));
+        let mut hires = Display::new((LO_WIDTH, LO_HEIGHT));
+        hires.set_hires(false);
+        assert_ne!(blank.hash(), hires.hash());
+        let mut drawn = Display::new((LO_WIDTH, LO_HEIGHT));
+        drawn.draw_at(3, 4);
+        assert_ne!(blank.hash(), drawn.hash());
+        assert_eq!(blank.hash(), Display::new((LO_WIDTH, LO_HEIGHT)).hash());
     }
 }
@@ -0,0 +1,100 @@
+// anti-flicker filters, which change what's shown but never the machine
+//
+// the `[filter]` section sets `mode` to one of
+//
+//   none   the default
+//   decay  pixels that go dark fade out instead of vanishing; `decay` is
+//          how much of the old brightness is kept each frame (default 0.6)
+//   or     a pixel stays lit while it was lit in any of the last `frames`
+//          frames (default 3); only for the monochrome displays
+
+use std::collections::VecDeque;
+
+use crate::config::Section;
+
+pub enum Filter {
+    None,
+    Decay {
+        keep: f32,
+        last: Vec<u32>,
+    },
+    Or {
+        frames: usize,
+        recent: VecDeque<Vec<bool>>,
+    },
+}
+
+impl Filter {
+    pub fn from_config(section: &Section) -> Result<Self, String> {
+        let filter = match section.get("mode").map_or("none", String::as_str) {
+            "none" => Filter::None,
+            "decay" => {
+                let val = section.get("decay").map_or("0.6", String::as_str);
+                let keep = val
+                    .parse()
+                    .ok()
+                    .filter(|keep| (0.0..1.0).contains(keep))
+                    .ok_or(format!("Invalid decay: {val}"))?;
+                Filter::Decay {
+                    keep,
+                    last: Vec::new(),
+                }
+            }
+            "or" => {
+                let val = section.get("frames").map_or("3", String::as_str);
+                let frames = val
+                    .parse()
+                    .ok()
+                    .filter(|&frames| frames > 0)
+                    .ok_or(format!("Invalid frames: {val}"))?;
+                Filter::Or {
+                    frames,
+                    recent: VecDeque::new(),
+                }
+            }
+            mode => return Err(format!("Unknown filter: {mode}")),
+        };
+        Ok(filter)
+    }
+    // before colouring in: keep recently lit pixels lit
+    pub fn hold(&mut self, lit: &mut [bool]) {
+        let Filter::Or { frames, recent } = self else {
+            return;
+        };
+        // the display changed size, so the old frames don't line up
+        if recent.front().is_some_and(|old| old.len() != lit.len()) {
+            recent.clear();
+        }
+        recent.push_front(lit.to_vec());
+        recent.truncate(*frames);
+        for old in recent.iter().skip(1) {
+            for (pixel, &was) in lit.iter_mut().zip(old) {
+                *pixel |= was;
+            }
+        }
+    }
+    // after colouring in: let pixels that aren't lit fade towards their
+    // new colour
+    pub fn fade(&mut self, frame: &mut [u32], lit: &[bool]) {
+        let Filter::Decay { keep, last } = self else {
+            return;
+        };
+        if last.len() == frame.len() {
+            for ((pixel, &old), &on) in frame.iter_mut().zip(last.iter()).zip(lit) {
+                if !on {
+                    *pixel = mix(old, *pixel, *keep);
+                }
+            }
+        }
+        last.clear();
+        last.extend_from_slice(frame);
+    }
+}
+
+fn mix(old: u32, new: u32, keep: f32) -> u32 {
+    let channel = |shift: u32| {
+        let (o, n) = ((old >> shift & 0xFF) as f32, (new >> shift & 0xFF) as f32);
+        ((o * keep + n * (1.0 - keep)).round() as u32) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
@@ -0,0 +1,200 @@
+// gamepad input for the hex keypad
+//
+// configured in the `[gamepad]` section as `<button> = <chip-8 key>`, using
+// SDL's button names (a, b, x, y, back, start, leftshoulder, dpup, ...);
+// a `[gamepad:rom]` section gives a rom its own profile
+// with no entries, the d-pad is 2/8/4/6 and a is 5
+
+use std::{fs, io};
+
+use sdl3::gamepad::{Button, Gamepad};
+
+use crate::config::Section;
+
+const DEFAULT_MAP: [(Button, u8); 5] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::South, 0x5),
+];
+
+const ALL_BUTTONS: [Button; 21] = [
+    Button::North,
+    Button::East,
+    Button::South,
+    Button::West,
+    Button::Back,
+    Button::Guide,
+    Button::Start,
+    Button::LeftStick,
+    Button::RightStick,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+    Button::Misc1,
+    Button::RightPaddle1,
+    Button::LeftPaddle1,
+    Button::RightPaddle2,
+    Button::LeftPaddle2,
+    Button::Touchpad,
+];
+
+// where button presses come from
+pub enum Pads {
+    // every controller SDL knows about, reopened as they come and go
+    Sdl {
+        subsystem: sdl3::GamepadSubsystem,
+        joysticks: sdl3::JoystickSubsystem,
+        open: Vec<Gamepad>,
+    },
+    // a script with one line of space separated button names per frame,
+    // for trying out mappings without a controller
+    Scripted {
+        frames: Vec<Vec<Button>>,
+        pos: usize,
+    },
+}
+
+pub struct Gamepads {
+    pads: Pads,
+    map: Vec<(Button, u8)>,
+}
+
+impl Pads {
+    pub fn sdl() -> Result<Self, String> {
+        let sdl_context = sdl3::init().map_err(|e| e.to_string())?;
+        Ok(Pads::Sdl {
+            subsystem: sdl_context.gamepad().map_err(|e| e.to_string())?,
+            joysticks: sdl_context.joystick().map_err(|e| e.to_string())?,
+            open: Vec::new(),
+        })
+    }
+    pub fn scripted(path: &str) -> io::Result<Self> {
+        let frames = parse_script(&fs::read_to_string(path)?)?;
+        Ok(Pads::Scripted { frames, pos: 0 })
+    }
+    fn pressed(&mut self) -> Vec<Button> {
+        match self {
+            Pads::Sdl {
+                subsystem,
+                joysticks,
+                open,
+            } => {
+                subsystem.update();
+                open.retain(Gamepad::attached);
+                if subsystem
+                    .num_gamepads()
+                    .is_ok_and(|n| n as usize != open.len())
+                {
+                    hotplug(subsystem, joysticks, open);
+                }
+                let mut pressed = Vec::new();
+                for pad in open.iter() {
+                    pressed.extend(ALL_BUTTONS.into_iter().filter(|&b| pad.button(b)));
+                }
+                pressed
+            }
+            Pads::Scripted { frames, pos } => {
+                // once the script runs out, nothing is pressed
+                let pressed = frames.get(*pos).cloned().unwrap_or_default();
+                *pos += 1;
+                pressed
+            }
+        }
+    }
+}
+
+// a misspelt button would quietly never be pressed, so it's an error
+fn parse_script(text: &str) -> io::Result<Vec<Vec<Button>>> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| {
+            line.split_whitespace()
+                .map(|name| {
+                    Button::from_string(name).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("line {}: unknown button {name}", n + 1),
+                        )
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// open any controller that isn't open yet
+fn hotplug(
+    subsystem: &sdl3::GamepadSubsystem,
+    joysticks: &sdl3::JoystickSubsystem,
+    open: &mut Vec<Gamepad>,
+) {
+    let Ok(instances) = joysticks.joysticks() else {
+        return;
+    };
+    for instance in instances {
+        let id = instance.id;
+        if !subsystem.is_game_controller(id) || open.iter().any(|pad| pad.instance_id() == id) {
+            continue;
+        }
+        if let Ok(pad) = subsystem.open(id) {
+            open.push(pad);
+        }
+    }
+}
+
+impl Gamepads {
+    pub fn from_config(pads: Pads, section: &Section) -> Result<Self, String> {
+        if section.is_empty() {
+            return Ok(Gamepads {
+                pads,
+                map: DEFAULT_MAP.to_vec(),
+            });
+        }
+        let mut map = Vec::new();
+        for (name, val) in section {
+            let button = Button::from_string(name).ok_or(format!("Unknown button: {name}"))?;
+            let key = u8::from_str_radix(val, 16)
+                .ok()
+                .filter(|&key| key <= 0xF)
+                .ok_or(format!("Unknown chip-8 key: {val}"))?;
+            map.push((button, key));
+        }
+        Ok(Gamepads { pads, map })
+    }
+    pub fn poll(&mut self) -> u16 {
+        let pressed = self.pads.pressed();
+        let mut state = 0;
+        for (button, key) in &self.map {
+            if pressed.contains(button) {
+                state |= 1 << key;
+            }
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script() {
+        let frames = parse_script("back dpup\n\nstart\n").unwrap();
+        assert_eq!(
+            frames,
+            [
+                vec![Button::Back, Button::DPadUp],
+                vec![],
+                vec![Button::Start]
+            ]
+        );
+        let e = parse_script("back\ndpup stratt\n").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "line 2: unknown button stratt");
+    }
+}
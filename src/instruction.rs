@@ -1,9 +1,14 @@
 use Instruction::*;
 
+use crate::cpu::Mode;
+
+#[derive(Clone, Copy)]
 pub enum Instruction {
     ScrollDown(u16),
     ScrollUp(u16),
     Clear,
+    // 0230, a machine code call that clears the screen in hires CHIP-8
+    HiresClear,
     Return,
     ScrollRight,
     ScrollLeft,
@@ -46,6 +51,65 @@ pub enum Instruction {
     SetPitch(u16),
     Store(usize),
     Load(usize),
+    // CHIP-8X
+    CycleBackground,
+    AddNibbles(usize, usize),
+    Color(usize, usize, usize),
+    SkipKey2(usize),
+    SkipNotKey2(usize),
+    // MegaChip
+    MegaOff,
+    MegaOn,
+    SetIndexLong(u8),
+    LoadPalette(u8),
+    SpriteWidth(u8),
+    SpriteHeight(u8),
+    ScreenAlpha(u8),
+    PlaySample(bool),
+    StopSample,
+    BlendMode(u8),
+    CollisionColor(u8),
+}
+
+impl Instruction {
+    // CHIP-8X and MegaChip reuse some words, so decoding depends on the mode
+    pub fn decode(word: u16, mode: Mode) -> Self {
+        match mode {
+            Mode::Chip8x => Instruction::decode_8x(word),
+            Mode::Mega => Instruction::decode_mega(word),
+            _ => Instruction::from(word),
+        }
+    }
+    fn decode_8x(word: u16) -> Self {
+        let x = ((word & 0xF00) >> 8) as usize;
+        let y = ((word & 0xF0) >> 4) as usize;
+        match word {
+            0x02A0 => CycleBackground,
+            0x5000..=0x5FFF if word & 0xF == 1 => AddNibbles(x, y),
+            0xB000..=0xBFFF => Color(x, y, (word & 0xF) as usize),
+            0xE000..=0xEFFF if word & 0xFF == 0xF2 => SkipKey2(x),
+            0xE000..=0xEFFF if word & 0xFF == 0xF5 => SkipNotKey2(x),
+            _ => Instruction::from(word),
+        }
+    }
+    fn decode_mega(word: u16) -> Self {
+        let nn = word as u8;
+        match word {
+            0x0010 => MegaOff,
+            0x0011 => MegaOn,
+            0x00B0..=0x00BF => ScrollUp(word & 0xF),
+            0x0100..=0x01FF => SetIndexLong(nn),
+            0x0200..=0x02FF => LoadPalette(nn),
+            0x0300..=0x03FF => SpriteWidth(nn),
+            0x0400..=0x04FF => SpriteHeight(nn),
+            0x0500..=0x05FF => ScreenAlpha(nn),
+            0x0600..=0x060F => PlaySample(word & 0xF == 0),
+            0x0700 => StopSample,
+            0x0800..=0x080F => BlendMode(nn),
+            0x0900..=0x09FF => CollisionColor(nn),
+            _ => Instruction::from(word),
+        }
+    }
 }
 
 impl std::convert::From<u16> for Instruction {
@@ -55,6 +119,7 @@ impl std::convert::From<u16> for Instruction {
             0x00C0..=0x00CF => ScrollDown(word & 0xF),
             0x00D0..=0x00DF => ScrollUp(word & 0xF),
             0x00E0 => Clear,
+            0x0230 => HiresClear,
             0x00EE => Return,
             0x00FB => ScrollRight,
             0x00FC => ScrollLeft,
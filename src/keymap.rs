@@ -0,0 +1,109 @@
+// mapping from host keys to the hex keypad
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+//
+// configured in the `[keypad]` section: `layout` picks a preset, and an
+// entry per chip-8 key (e.g. `5 = W Up`) replaces that key's host keys;
+// CHIP-8X's second keypad is set up the same way in `[keypad2]`, which
+// starts out with no keys
+
+use minifb::{Key, Key::*, Window};
+
+use crate::config::Section;
+
+pub struct KeyMap {
+    // host keys for each chip-8 key, indexed by chip-8 key
+    keys: [Vec<Key>; 16],
+}
+
+// presets, listed in chip-8 key order 0-F
+const QWERTY: [Key; 16] = [X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V];
+const AZERTY: [Key; 16] = [X, Key1, Key2, Key3, A, Z, E, Q, S, D, W, C, Key4, R, F, V];
+const DVORAK: [Key; 16] = [
+    Q, Key1, Key2, Key3, Apostrophe, Comma, Period, A, O, E, Semicolon, J, Key4, P, U, K,
+];
+// the numpad preset keeps the keypad's shape, so 2/4/6/8 are the numpad arrows
+#[rustfmt::skip]
+const NUMPAD: [Key; 16] = [
+    NumPadDot, NumPad7, NumPad8, NumPad9, NumPad4, NumPad5, NumPad6, NumPad1,
+    NumPad2, NumPad3, NumPad0, NumPadEnter, NumPadSlash, NumPadAsterisk, NumPadMinus, NumPadPlus,
+];
+
+// every key minifb knows about, for looking keys up by name
+#[rustfmt::skip]
+const ALL_KEYS: [Key; 106] = [
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15,
+    Down, Left, Right, Up,
+    Apostrophe, Backquote, Backslash, Comma, Equal, LeftBracket, Minus, Period, RightBracket,
+    Semicolon, Slash, Backspace, Delete, End, Enter, Escape, Home, Insert, Menu, PageDown,
+    PageUp, Pause, Space, Tab, NumLock, CapsLock, ScrollLock,
+    LeftShift, RightShift, LeftCtrl, RightCtrl,
+    NumPad0, NumPad1, NumPad2, NumPad3, NumPad4, NumPad5, NumPad6, NumPad7, NumPad8, NumPad9,
+    NumPadDot, NumPadSlash, NumPadAsterisk, NumPadMinus, NumPadPlus, NumPadEnter,
+    LeftAlt, RightAlt, LeftSuper, RightSuper,
+];
+
+impl KeyMap {
+    pub fn preset(name: &str) -> Option<Self> {
+        let layout = match name {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "dvorak" => DVORAK,
+            "numpad" => NUMPAD,
+            "none" => {
+                return Some(KeyMap {
+                    keys: Default::default(),
+                });
+            }
+            _ => return None,
+        };
+        Some(KeyMap {
+            keys: layout.map(|key| vec![key]),
+        })
+    }
+    pub fn from_config(section: &Section, default_layout: &str) -> Result<Self, String> {
+        let layout = section.get("layout").map_or(default_layout, String::as_str);
+        let mut keymap = KeyMap::preset(layout).ok_or(format!("Unknown layout: {layout}"))?;
+        for (name, val) in section {
+            if name == "layout" {
+                continue;
+            }
+            let key = u8::from_str_radix(name, 16)
+                .ok()
+                .filter(|&key| key <= 0xF)
+                .ok_or(format!("Unknown chip-8 key: {name}"))?;
+            keymap.keys[key as usize] = val
+                .split([' ', ','])
+                .filter(|s| !s.is_empty())
+                .map(|s| parse_key(s).ok_or(format!("Unknown host key: {s}")))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(keymap)
+    }
+    pub fn poll(&self, window: &Window) -> u16 {
+        let mut state = 0;
+        for (i, keys) in self.keys.iter().enumerate() {
+            if keys.iter().any(|&key| window.is_key_down(key)) {
+                state |= 1 << i;
+            }
+        }
+        state
+    }
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    // bare digits are more natural to write than `Key1`
+    let name = if name.len() == 1 && name.as_bytes()[0].is_ascii_digit() {
+        format!("Key{name}")
+    } else {
+        name.to_string()
+    };
+    ALL_KEYS
+        .into_iter()
+        .find(|key| format!("{key:?}").eq_ignore_ascii_case(&name))
+}
@@ -0,0 +1,48 @@
+// hex keypad, sampled once per frame
+// bit n of each mask stands for key n; CHIP-8X's second keypad is in the
+// upper 16 bits of the state
+
+pub struct Keypad {
+    state: u32,
+    // keys that went down/up since the previous frame; Fx0A consumes these
+    // so that one press can't satisfy two waits
+    pressed: u16,
+    released: u16,
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Keypad {
+            state: 0,
+            pressed: 0,
+            released: 0,
+        }
+    }
+    pub fn key_pressed(&self, key: u8) -> bool {
+        // there is no key above F, so it can never be down
+        key <= 0xF && self.state & (1 << key) > 0
+    }
+    pub fn key2_pressed(&self, key: u8) -> bool {
+        key <= 0xF && self.state & (1 << (key + 16)) > 0
+    }
+    // Fx0A only looks at the first keypad
+    pub fn set_state(&mut self, state: u32) {
+        self.pressed = (state & !self.state) as u16;
+        self.released = (!state & self.state) as u16;
+        self.state = state;
+    }
+    // lowest key that was pressed this frame
+    pub fn take_press(&mut self) -> Option<u8> {
+        if self.pressed == 0 {
+            return None;
+        }
+        let key = self.pressed.trailing_zeros() as u8;
+        self.pressed &= !(1 << key);
+        Some(key)
+    }
+    pub fn take_release(&mut self, key: u8) -> bool {
+        let released = self.released & (1 << key) > 0;
+        self.released &= !(1 << key);
+        released
+    }
+}
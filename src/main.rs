@@ -1,70 +1,413 @@
-use std::{env::args, fs::File, process::ExitCode, thread::sleep, time::Duration};
+use std::{env::args, fs, path::Path, process::ExitCode};
 
-use minifb::Key;
+use minifb::{Key, KeyRepeat};
 
+use audio::Audio;
+use config::Config;
 use cpu::{Cpu, Mode};
-use display::Display;
+use debug::Labels;
+use display::{Display, HI_HEIGHT, HI_WIDTH, MEGA_HEIGHT, MEGA_WIDTH};
+use filter::Filter;
+use gamepad::{Gamepads, Pads};
+use keymap::KeyMap;
+use movie::Movie;
+use quirks::Quirks;
+use rng::{Rng, RngKind};
+use scheduler::{Scheduler, Timing};
+use screen::Screen;
+use speed::Speed;
+use theme::Themes;
 
 mod audio;
+mod bench;
+mod block;
+mod config;
 mod cpu;
+mod debug;
+mod detect;
 mod display;
+mod filter;
+mod gamepad;
 mod instruction;
+mod keymap;
+mod keypad;
+mod movie;
+mod quirks;
+mod rng;
+mod scheduler;
+mod screen;
+mod shader;
+mod speed;
+mod theme;
+mod timing;
 
-// const SCALE: u64 = 10000;
 const IPS: u64 = 720;
-// const IPS: u64 = 720 * SCALE;
-// const FPS: u64 = 6000;
-// const IPS: u64 = 720;
 // const IPS: u64 = 20000;
-const FPS: u64 = 60;
-// const FPS: u64 = 60 * SCALE;
-const CPF: u64 = IPS / FPS;
-// const CPF: u64 = 5000;
 
-fn main() -> ExitCode {
+struct Options {
+    // None to guess from the rom
+    mode: Option<Mode>,
+    fname: String,
+    record: Option<String>,
+    replay: Option<String>,
+    rng: RngKind,
+    seed: Option<u64>,
+    config: Option<String>,
+    pad_script: Option<String>,
+    timing: Timing,
+}
+
+fn parse_ips(val: &str) -> Result<Timing, String> {
+    val.parse()
+        .ok()
+        .filter(|&ips| ips > 0)
+        .map(Timing::Ips)
+        .ok_or(format!("Invalid ips: {val}"))
+}
+
+// `auto` is None
+fn parse_mode(name: &str) -> Result<Option<Mode>, String> {
+    match name {
+        "auto" => Ok(None),
+        _ => Mode::from_name(name)
+            .map(Some)
+            .ok_or(format!("Unknown mode: {name}")),
+    }
+}
+
+// the mode that was asked for, or else the one the rom looks like
+fn pick_mode(mode: Option<Mode>, rom: &[u8]) -> Mode {
+    let detected = detect::detect(rom);
+    match mode {
+        Some(mode) => {
+            // nearly anything looks like a cosmac rom, so that's no hint
+            if mode != detected && detected != Mode::Cosmac {
+                eprintln!(
+                    "Note: this rom looks like a {} rom, try --mode {}",
+                    detected.name(),
+                    detected.name()
+                );
+            }
+            mode
+        }
+        None => {
+            eprintln!("Detected {} mode", detected.name());
+            detected
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
     let exec_name = args().next().unwrap();
-    let mode = if let Some(arg) = args().nth(1) {
+    let usage = format!(
+        "Usage: {exec_name} [--record <movie> | --replay <movie>] \
+         [--rng std|vip|hp48] [--seed <n>] [--config <file>] \
+         [--pad-script <file>] [--ips <n> | --vip-timing] [--mode <mode>] [<mode>] <file>"
+    );
+    let mut positional = Vec::new();
+    let mut record = None;
+    let mut replay = None;
+    let mut rng = RngKind::Std;
+    let mut seed = None;
+    let mut config = None;
+    let mut pad_script = None;
+    let mut timing = Timing::Ips(IPS);
+    let mut mode = None;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
         match arg.as_str() {
-            "c" | "cosmac" => Mode::Cosmac,
-            "s" | "super" => Mode::Super,
-            "x" | "xo" => Mode::Xo,
-            _ => {
-                eprintln!("Unknown mode: {arg}");
-                return ExitCode::FAILURE;
+            "--record" => record = Some(args.next().ok_or(&usage)?),
+            "--replay" => replay = Some(args.next().ok_or(&usage)?),
+            "--rng" => {
+                let name = args.next().ok_or(&usage)?;
+                rng = RngKind::from_name(&name).ok_or(format!("Unknown rng: {name}"))?;
             }
+            "--seed" => {
+                let val = args.next().ok_or(&usage)?;
+                seed = Some(val.parse().map_err(|_| format!("Invalid seed: {val}"))?);
+            }
+            "--config" => config = Some(args.next().ok_or(&usage)?),
+            "--pad-script" => pad_script = Some(args.next().ok_or(&usage)?),
+            "--ips" => {
+                timing = parse_ips(&args.next().ok_or(&usage)?)?;
+            }
+            "--vip-timing" => timing = Timing::Vip,
+            "--mode" => mode = parse_mode(&args.next().ok_or(&usage)?)?,
+            _ => positional.push(arg),
         }
-    } else {
-        eprintln!("Usage: {exec_name} <mode> <file>");
+    }
+    if record.is_some() && replay.is_some() {
+        return Err(usage);
+    }
+    // the mode can still be given before the file, as it used to be
+    let fname = match <[String; 2]>::try_from(positional) {
+        Ok([name, fname]) => {
+            mode = parse_mode(&name)?;
+            fname
+        }
+        Err(mut positional) if positional.len() == 1 => positional.pop().unwrap(),
+        Err(_) => return Err(usage),
+    };
+    Ok(Options {
+        mode,
+        fname,
+        record,
+        replay,
+        rng,
+        seed,
+        config,
+        pad_script,
+        timing,
+    })
+}
+
+fn main() -> ExitCode {
+    if args().nth(1).is_some_and(|arg| arg == "bench") {
+        return bench::run();
+    }
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let fname = &opts.fname;
+    let Ok(rom) = fs::read(fname) else {
+        eprintln!("Unable to open file: {fname}");
         return ExitCode::FAILURE;
     };
-    let Some(fname) = args().nth(2) else {
-        eprintln!("Usage: {exec_name} <mode> <file>");
+    let mode = pick_mode(opts.mode, &rom);
+    if opts.timing == Timing::Vip && mode != Mode::Cosmac {
+        eprintln!("VIP timing is only available in Cosmac mode");
         return ExitCode::FAILURE;
+    }
+    // the default config file is optional, one given explicitly is not
+    let config = match &opts.config {
+        Some(path) => Config::load(path, fname),
+        None if Path::new(config::DEFAULT_PATH).exists() => {
+            Config::load(config::DEFAULT_PATH, fname)
+        }
+        None => Ok(Config::empty(fname)),
     };
-    let Ok(mut program) = File::open(&fname) else {
-        eprintln!("Unable to open file: {fname}");
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Unable to read config: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let keymap = match KeyMap::from_config(&config.section("keypad"), "qwerty") {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let keymap2 = match KeyMap::from_config(&config.section("keypad2"), "none") {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // controllers are optional, so failing to reach them is only a warning
+    let pads = match &opts.pad_script {
+        Some(path) => match Pads::scripted(path) {
+            Ok(pads) => Some(pads),
+            Err(e) => {
+                eprintln!("Unable to read gamepad script {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Pads::sdl()
+            .inspect_err(|e| eprintln!("Gamepads unavailable: {e}"))
+            .ok(),
+    };
+    let mut gamepads =
+        match pads.map(|pads| Gamepads::from_config(pads, &config.section("gamepad"))) {
+            Some(Ok(gamepads)) => Some(gamepads),
+            Some(Err(e)) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+            None => None,
+        };
+    let quirks = match Quirks::from_config(mode, &config.section("quirks")) {
+        Ok(quirks) => quirks,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let themes = match Themes::from_config(&config.section("theme")) {
+        Ok(themes) => themes,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let filter = match Filter::from_config(&config.section("filter")) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let labels = match Labels::from_config(&config.section("labels")) {
+        Ok(labels) => labels,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut speed = match Speed::from_config(
+        &config.section("speed"),
+        opts.record.is_none() && opts.replay.is_none(),
+    ) {
+        Ok(speed) => speed,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut replay = match &opts.replay {
+        Some(path) => match Movie::load(path) {
+            Ok(movie) => {
+                if let Err(e) = movie.check(mode, &rom, &quirks) {
+                    eprintln!("Unable to replay {path}: {e}");
+                    return ExitCode::FAILURE;
+                }
+                Some(movie)
+            }
+            Err(e) => {
+                eprintln!("Unable to read movie {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    // a replay always uses the generator and speed it was recorded with
+    let (rng, seed, timing) = match &replay {
+        Some(movie) => (movie.rng, movie.seed, movie.timing),
+        None => (
+            opts.rng,
+            opts.seed.unwrap_or_else(rand::random),
+            opts.timing,
+        ),
+    };
+    let mut recording = opts
+        .record
+        .as_ref()
+        .map(|_| Movie::new(mode, &rom, &quirks, rng, seed, timing));
+    let mut cpu = Cpu::with_mode(mode, quirks, Rng::new(rng, seed), Audio::new());
+    if let Err(e) = cpu.load(&mut rom.as_slice()) {
+        eprintln!("Unable to load {fname}: {e}");
         return ExitCode::FAILURE;
+    }
+    let mut disp = Display::new(mode.resolution());
+    if mode == Mode::Chip8x {
+        disp.enable_color();
+    }
+    let size = if mode == Mode::Mega {
+        (MEGA_WIDTH, MEGA_HEIGHT)
+    } else {
+        (HI_WIDTH, HI_HEIGHT)
+    };
+    let mut screen = match Screen::new(
+        size,
+        themes,
+        filter,
+        &config.section("shader"),
+        &config.section("window"),
+    ) {
+        Ok(screen) => screen,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
     };
-    let mut cpu = Cpu::with_mode(mode);
-    if cpu.load(&mut program).is_err() {
-        eprintln!("Unable to read file: {}", fname);
+    let mut sched = Scheduler::new(timing);
+    screen.window.set_title(&speed.title(&sched));
+    while screen.window.is_open() && !screen.window.is_key_down(Key::Escape) {
+        if !speed.poll(&mut screen.window, &mut sched) {
+            // keep the window responsive while paused
+            screen.window.update();
+            sched.wait();
+            continue;
+        }
+        sched.run_frame(&mut cpu, &mut disp);
+        screen.present(&disp);
+        // still save the recording on the way out; it's what reproduces
+        // the fault
+        if let Some(fault) = cpu.fault {
+            eprintln!("Machine fault: {fault}");
+            eprint!("{}", debug::dump(&cpu, &labels));
+            break;
+        }
+        if screen.window.is_key_pressed(Key::F9, KeyRepeat::No) {
+            screen.themes.next();
+        }
+        if screen.window.is_key_pressed(Key::F10, KeyRepeat::No) {
+            screen.shader.next();
+        }
+        if screen.window.is_key_pressed(Key::F11, KeyRepeat::No) {
+            screen.toggle_fullscreen();
+            screen.window.set_title(&speed.title(&sched));
+        }
+        // F12 prints the machine state and call stack
+        if screen.window.is_key_pressed(Key::F12, KeyRepeat::No) {
+            eprint!("{}", debug::dump(&cpu, &labels));
+        }
+        // input is sampled once per frame so that it can be replayed
+        let keys = match &mut replay {
+            Some(movie) => match movie.next_frame() {
+                Some(keys) => keys,
+                None => break,
+            },
+            None => {
+                let keys =
+                    keymap.poll(&screen.window) | gamepads.as_mut().map_or(0, Gamepads::poll);
+                keys as u32 | (keymap2.poll(&screen.window) as u32) << 16
+            }
+        };
+        if let Some(movie) = &mut recording {
+            movie.record(keys);
+        }
+        cpu.keypad.set_state(keys);
+        if replay.as_ref().is_some_and(Movie::finished) {
+            break;
+        }
+        sched.wait();
+    }
+    screen.save_geometry();
+    if let (Some(movie), Some(path)) = (&mut recording, &opts.record) {
+        movie.hash = Some(disp.hash());
+        if let Err(e) = movie.save(path) {
+            eprintln!("Unable to write movie {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    if cpu.fault.is_some() {
         return ExitCode::FAILURE;
     }
-    let mut disp = Display::with_fps(FPS as usize);
-    // let mut disp = Display::with_fps(60);
-    let mut cycs = 0;
-    while disp.window.is_open() && !disp.window.is_key_down(Key::Escape) {
-        let ins = cpu.fetch();
-        cpu.execute(&ins, &mut disp);
-        cycs += 1;
-        if cycs == CPF {
-            disp.update();
-            cpu.dec_timers();
-            cycs = 0;
-        } else {
-            disp.just_updated = false;
-        }
-        sleep(Duration::from_nanos(1e9 as u64 / IPS));
+    if let Some(movie) = &replay {
+        if !movie.finished() {
+            eprintln!("Replay stopped before the end of the movie");
+            return ExitCode::FAILURE;
+        }
+        match movie.hash {
+            Some(hash) if hash != disp.hash() => {
+                eprintln!(
+                    "Replay diverged: framebuffer hash {:016x}, expected {:016x}",
+                    disp.hash(),
+                    hash
+                );
+                return ExitCode::FAILURE;
+            }
+            Some(_) => println!("Replay matched"),
+            None => println!("Replay finished (movie has no hash to verify)"),
+        }
     }
     ExitCode::SUCCESS
 }
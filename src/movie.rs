@@ -0,0 +1,189 @@
+// input movies: the rng seed plus the keypad state of every frame,
+// which is everything needed to replay a run exactly, along with what the
+// run has to match for that to hold
+//
+// file format (text, one entry per line):
+//   mode <mode name>
+//   rom <FNV-1a hash of the rom as 16 hex digits>
+//   quirks <every quirk as name=value, space separated>
+//   rng <generator name>
+//   seed <decimal>
+//   ips <instructions per second>, or `timing vip` for VIP cycle timing
+//   <keypad state in hex>   (repeated, one per frame; a CHIP-8X second
+//                            keypad is in the upper 16 bits)
+//   hash <framebuffer hash as 16 hex digits>
+
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+};
+
+use crate::{cpu::Mode, quirks::Quirks, rng::RngKind, scheduler::Timing};
+
+pub struct Movie {
+    mode: Mode,
+    rom: u64,
+    quirks: String,
+    pub rng: RngKind,
+    pub seed: u64,
+    pub timing: Timing,
+    pub hash: Option<u64>,
+    frames: Vec<u32>,
+    pos: usize,
+}
+
+impl Movie {
+    pub fn new(
+        mode: Mode,
+        rom: &[u8],
+        quirks: &Quirks,
+        rng: RngKind,
+        seed: u64,
+        timing: Timing,
+    ) -> Self {
+        Movie {
+            mode,
+            rom: fnv(rom),
+            quirks: quirks.to_string(),
+            rng,
+            seed,
+            timing,
+            hash: None,
+            frames: Vec::new(),
+            pos: 0,
+        }
+    }
+    pub fn load(path: &str) -> Result<Self> {
+        let invalid = |line: &str| Error::new(ErrorKind::InvalidData, format!("bad line: {line}"));
+        let (mut mode, mut rom, mut quirks) = (None, None, None);
+        let (mut rng, mut seed, mut timing, mut hash) = (None, None, None, None);
+        let mut frames = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(val) = line.strip_prefix("mode ") {
+                mode = Some(Mode::from_name(val).ok_or_else(|| invalid(line))?);
+            } else if let Some(val) = line.strip_prefix("rom ") {
+                rom = Some(u64::from_str_radix(val, 16).map_err(|_| invalid(line))?);
+            } else if let Some(val) = line.strip_prefix("quirks ") {
+                quirks = Some(val.to_string());
+            } else if let Some(val) = line.strip_prefix("rng ") {
+                rng = Some(RngKind::from_name(val).ok_or_else(|| invalid(line))?);
+            } else if let Some(val) = line.strip_prefix("seed ") {
+                seed = Some(val.parse().map_err(|_| invalid(line))?);
+            } else if let Some(val) = line.strip_prefix("ips ") {
+                timing = Some(Timing::Ips(val.parse().map_err(|_| invalid(line))?));
+            } else if line == "timing vip" {
+                timing = Some(Timing::Vip);
+            } else if let Some(val) = line.strip_prefix("hash ") {
+                hash = Some(u64::from_str_radix(val, 16).map_err(|_| invalid(line))?);
+            } else {
+                frames.push(u32::from_str_radix(line, 16).map_err(|_| invalid(line))?);
+            }
+        }
+        Ok(Movie {
+            mode: mode.ok_or_else(|| invalid("missing mode"))?,
+            rom: rom.ok_or_else(|| invalid("missing rom"))?,
+            quirks: quirks.ok_or_else(|| invalid("missing quirks"))?,
+            rng: rng.ok_or_else(|| invalid("missing rng"))?,
+            seed: seed.ok_or_else(|| invalid("missing seed"))?,
+            timing: timing.ok_or_else(|| invalid("missing ips or timing"))?,
+            hash,
+            frames,
+            pos: 0,
+        })
+    }
+    // a movie only replays the same way on the machine it was recorded on
+    pub fn check(
+        &self,
+        mode: Mode,
+        rom: &[u8],
+        quirks: &Quirks,
+    ) -> std::result::Result<(), String> {
+        if mode != self.mode {
+            return Err(format!(
+                "movie was recorded in {} mode, not {} mode",
+                self.mode.name(),
+                mode.name()
+            ));
+        }
+        if fnv(rom) != self.rom {
+            return Err("movie was recorded with a different rom".to_string());
+        }
+        let quirks = quirks.to_string();
+        if quirks != self.quirks {
+            return Err(format!(
+                "movie was recorded with quirks {}, not {quirks}",
+                self.quirks
+            ));
+        }
+        Ok(())
+    }
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut out = format!(
+            "mode {}\nrom {:016x}\nquirks {}\n",
+            self.mode.name(),
+            self.rom,
+            self.quirks
+        );
+        out += &format!("rng {}\nseed {}\n", self.rng.name(), self.seed);
+        out += &match self.timing {
+            Timing::Ips(ips) => format!("ips {ips}\n"),
+            Timing::Vip => "timing vip\n".to_string(),
+        };
+        for frame in &self.frames {
+            out += &format!("{:04x}\n", frame);
+        }
+        if let Some(hash) = self.hash {
+            out += &format!("hash {:016x}\n", hash);
+        }
+        fs::write(path, out)
+    }
+    pub fn finished(&self) -> bool {
+        self.pos >= self.frames.len()
+    }
+    pub fn next_frame(&mut self) -> Option<u32> {
+        let frame = self.frames.get(self.pos).copied();
+        self.pos += 1;
+        frame
+    }
+    pub fn record(&mut self, keys: u32) {
+        self.frames.push(keys);
+    }
+}
+
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF29CE484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_machine() {
+        let rom = [0x12, 0x00];
+        let quirks = Quirks::for_mode(Mode::Super);
+        let mut movie = Movie::new(Mode::Super, &rom, &quirks, RngKind::Vip, 7, Timing::Vip);
+        movie.record(0x0010);
+        let path = std::env::temp_dir().join(format!("chip8-movie-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        movie.save(path).unwrap();
+        let mut movie = Movie::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(movie.check(Mode::Super, &rom, &quirks).is_ok());
+        assert!(movie.check(Mode::Xo, &rom, &quirks).is_err());
+        assert!(movie.check(Mode::Super, &[0x12, 0x02], &quirks).is_err());
+        let mut other = Quirks::for_mode(Mode::Super);
+        other.wrap_sprites = true;
+        assert!(movie.check(Mode::Super, &rom, &other).is_err());
+        assert!(movie.rng == RngKind::Vip && movie.seed == 7);
+        assert_eq!(movie.next_frame(), Some(0x0010));
+        assert!(movie.finished());
+    }
+}
@@ -0,0 +1,134 @@
+// behaviour that differs between interpreters
+// each mode has its own defaults, which the `[quirks]` section can override
+
+use std::fmt;
+
+use crate::{config::Section, cpu::Mode};
+
+pub struct Quirks {
+    // Fx0A finishes when the key is released rather than when it's pressed
+    pub get_key_release: bool,
+    // the sound timer holds still while Fx0A waits
+    pub get_key_pauses_sound: bool,
+    // how many calls deep subroutines can go
+    pub stack_size: usize,
+    // return addresses live in memory at 0xEA0 like on the VIP, so programs
+    // can (accidentally or not) overwrite them
+    pub stack_in_memory: bool,
+    // addresses past the end of memory wrap around to the start instead of
+    // faulting
+    pub wrap_memory: bool,
+    // where programs are loaded and start running
+    pub load_address: u16,
+    // sprites that go off one edge come back on the other instead of being
+    // clipped
+    pub wrap_sprites: bool,
+    // in hires, VF counts the sprite rows that collided or were clipped off
+    // the bottom, like SUPER-CHIP 1.1, instead of just being 1
+    pub collision_rows: bool,
+    // drawing a sprite in lores ends the frame, like the VIP's interpreter
+    // waiting for the vertical blank before it draws
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub fn for_mode(mode: Mode) -> Self {
+        // the VIP interpreter's; its variants only move where programs load
+        let vip = Quirks {
+            get_key_release: true,
+            get_key_pauses_sound: true,
+            stack_size: 12,
+            stack_in_memory: false,
+            wrap_memory: true,
+            load_address: 0x200,
+            wrap_sprites: false,
+            collision_rows: false,
+            display_wait: true,
+        };
+        match mode {
+            Mode::Cosmac | Mode::Hires | Mode::Chip10 => vip,
+            Mode::Chip8x => Quirks {
+                load_address: 0x300,
+                ..vip
+            },
+            Mode::Eti => Quirks {
+                load_address: 0x600,
+                ..vip
+            },
+            Mode::Super | Mode::Mega => Quirks {
+                get_key_release: false,
+                get_key_pauses_sound: false,
+                stack_size: 16,
+                wrap_memory: false,
+                collision_rows: true,
+                // SUPER-CHIP 1.1 still waits in lores
+                display_wait: mode == Mode::Super,
+                ..vip
+            },
+            Mode::Xo => Quirks {
+                get_key_pauses_sound: false,
+                stack_size: 16,
+                wrap_sprites: true,
+                display_wait: false,
+                ..vip
+            },
+        }
+    }
+    pub fn from_config(mode: Mode, section: &Section) -> Result<Self, String> {
+        let mut quirks = Quirks::for_mode(mode);
+        for (name, val) in section {
+            match name.as_str() {
+                "get_key_release" => quirks.get_key_release = parse_bool(name, val)?,
+                "get_key_pauses_sound" => quirks.get_key_pauses_sound = parse_bool(name, val)?,
+                "stack_size" => {
+                    quirks.stack_size = val
+                        .parse()
+                        .map_err(|_| format!("Invalid value for quirk {name}: {val}"))?
+                }
+                "stack_in_memory" => quirks.stack_in_memory = parse_bool(name, val)?,
+                "wrap_memory" => quirks.wrap_memory = parse_bool(name, val)?,
+                "wrap_sprites" => quirks.wrap_sprites = parse_bool(name, val)?,
+                "collision_rows" => quirks.collision_rows = parse_bool(name, val)?,
+                "display_wait" => quirks.display_wait = parse_bool(name, val)?,
+                "load_address" => {
+                    quirks.load_address = val
+                        .strip_prefix("0x")
+                        .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                        .filter(|&addr| (addr as usize) < mode.mem_size())
+                        .ok_or(format!("Invalid value for quirk {name}: {val}"))?
+                }
+                _ => return Err(format!("Unknown quirk: {name}")),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+// every quirk as `name=value`, the way `[quirks]` would set it
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "get_key_release={} get_key_pauses_sound={} stack_size={} stack_in_memory={} \
+             wrap_memory={} load_address={:#x} wrap_sprites={} collision_rows={} \
+             display_wait={}",
+            self.get_key_release,
+            self.get_key_pauses_sound,
+            self.stack_size,
+            self.stack_in_memory,
+            self.wrap_memory,
+            self.load_address,
+            self.wrap_sprites,
+            self.collision_rows,
+            self.display_wait
+        )
+    }
+}
+
+fn parse_bool(name: &str, val: &str) -> Result<bool, String> {
+    match val {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("Invalid value for quirk {name}: {val}")),
+    }
+}
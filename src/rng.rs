@@ -0,0 +1,121 @@
+// random number generators for Cxkk
+// all of them are seeded so that runs can be reproduced
+
+use rand::{Rng as _, SeedableRng, rngs::StdRng};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RngKind {
+    Std,
+    Vip,
+    Hp48,
+}
+
+impl RngKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "std" => Some(RngKind::Std),
+            "vip" | "cosmac" => Some(RngKind::Vip),
+            "hp48" | "super" => Some(RngKind::Hp48),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            RngKind::Std => "std",
+            RngKind::Vip => "vip",
+            RngKind::Hp48 => "hp48",
+        }
+    }
+}
+
+pub enum Rng {
+    Std(Box<StdRng>),
+    // the VIP interpreter keeps its seed in R9: each Cxkk increments it,
+    // adds R9.1 to the byte of the interpreter's own code at 0x1xx picked
+    // by R9.0, then adds that sum shifted right through the carry, and
+    // keeps the result in R9.1
+    Vip { r9: u16 },
+    // the HP-48's RAND is a decimal LCG, x = 2851130928467 * x mod 10^15
+    Hp48 { x: u64 },
+}
+
+// the second page of the VIP interpreter (0x100-0x1FF), which Cxkk
+// itself lives in at 0x1D9
+const VIP_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC,
+    0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A,
+    0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA,
+    0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A,
+    0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F,
+    0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA,
+    0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88,
+    0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88,
+    0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2,
+    0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A,
+    0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2,
+    0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE,
+    0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F,
+    0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+const HP48_MOD: u64 = 1_000_000_000_000_000;
+const HP48_MUL: u64 = 2_851_130_928_467;
+
+impl Rng {
+    pub fn new(kind: RngKind, seed: u64) -> Self {
+        match kind {
+            RngKind::Std => Rng::Std(Box::new(StdRng::seed_from_u64(seed))),
+            RngKind::Vip => Rng::Vip { r9: seed as u16 },
+            // the seed must not be a multiple of 10 or the sequence dies out
+            RngKind::Hp48 => Rng::Hp48 {
+                x: (seed % (HP48_MOD / 10)) * 10 + 1,
+            },
+        }
+    }
+    pub fn next(&mut self) -> u8 {
+        match self {
+            Rng::Std(rng) => rng.random(),
+            Rng::Vip { r9 } => {
+                *r9 = r9.wrapping_add(1);
+                let [hi, lo] = r9.to_be_bytes();
+                // ADD, SHRC, ADD
+                let (sum, carry) = hi.overflowing_add(VIP_PAGE[lo as usize]);
+                let hi = ((carry as u8) << 7 | sum >> 1).wrapping_add(sum);
+                *r9 = u16::from_be_bytes([hi, lo]);
+                hi
+            }
+            Rng::Hp48 { x } => {
+                *x = ((*x as u128 * HP48_MUL as u128) % HP48_MOD as u128) as u64;
+                // RAND returns x / 10^15, scale that to a byte
+                (*x as u128 * 256 / HP48_MOD as u128) as u8
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // worked out separately by stepping through the 1802 code at 0x1D9
+    #[test]
+    fn vip_sequence() {
+        let mut rng = Rng::new(RngKind::Vip, 0x1234);
+        let out: Vec<u8> = (0..16).map(|_| rng.next()).collect();
+        assert_eq!(
+            out,
+            [
+                57, 50, 104, 16, 64, 101, 214, 104, 16, 24, 171, 21, 143, 47, 183, 147
+            ]
+        );
+        let mut rng = Rng::new(RngKind::Vip, 0);
+        let out: Vec<u8> = (0..16).map(|_| rng.next()).collect();
+        assert_eq!(
+            out,
+            [
+                0, 0, 0, 0, 103, 143, 186, 152, 34, 167, 188, 52, 194, 2, 5, 58
+            ]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+// paces emulation in 60 Hz frames
+//
+// each frame spends a budget of instructions (or VIP machine cycles), then
+// ticks the timers and presents the display; frames are scheduled against
+// fixed deadlines on a monotonic clock so that oversleeping one frame is
+// made up in the next, rather than sleeping after every instruction and
+// letting errors pile up
+
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{cpu::Cpu, display::Display, timing::VIP_FRAME_CYCLES};
+
+pub const FRAME_RATE: u64 = 60;
+// how far behind we're allowed to fall before giving up on catching up
+// (e.g. after the window was dragged or the machine was suspended)
+const MAX_LAG: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Timing {
+    // a fixed number of instructions per second
+    Ips(u64),
+    // each instruction costs what it did on the COSMAC VIP
+    Vip,
+}
+
+// how fast emulated frames go by compared to real ones
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pace {
+    Normal,
+    Fast(u32),
+    Slow(u32),
+    Uncapped,
+}
+
+pub struct Scheduler {
+    pub timing: Timing,
+    pub pace: Pace,
+    frame_len: Duration,
+    deadline: Instant,
+    // what's left to spend this frame; goes negative when an instruction
+    // overruns the frame, and the next frame pays that back
+    budget: i64,
+    // instructions owed from ips not dividing evenly into frames, in
+    // 1/FRAME_RATE units
+    carry: u64,
+}
+
+impl Scheduler {
+    pub fn new(timing: Timing) -> Self {
+        let frame_len = Duration::from_nanos(1_000_000_000 / FRAME_RATE);
+        Scheduler {
+            timing,
+            pace: Pace::Normal,
+            frame_len,
+            deadline: Instant::now() + frame_len,
+            budget: 0,
+            carry: 0,
+        }
+    }
+    // run this frame's instructions and tick the timers, returning how many
+    // instructions ran; a fault cuts the frame short
+    pub fn run_frame(&mut self, cpu: &mut Cpu, disp: &mut Display) -> u64 {
+        self.start_frame();
+        let mut count = 0;
+        while self.budget > 0 {
+            if cpu.fault.is_some() {
+                return count;
+            }
+            match self.timing {
+                Timing::Ips(_) => {
+                    let ran = cpu.run_block(self.budget as u64, disp);
+                    self.spend(ran as u32);
+                    count += ran;
+                    if cpu.take_display_wait() {
+                        self.end_frame();
+                    }
+                }
+                Timing::Vip => {
+                    match cpu.step_vip(disp) {
+                        // the sprite is drawn once the interrupt comes, so
+                        // the next frame pays for it
+                        Some(cycles) if cpu.take_display_wait() => {
+                            self.end_frame();
+                            self.spend(cycles);
+                        }
+                        Some(cycles) => self.spend(cycles),
+                        // the VIP sits idle until the next interrupt
+                        None => self.end_frame(),
+                    }
+                    count += 1;
+                }
+            }
+        }
+        cpu.dec_timers();
+        count
+    }
+    fn start_frame(&mut self) {
+        match self.timing {
+            Timing::Ips(ips) => {
+                self.carry += ips;
+                self.budget += (self.carry / FRAME_RATE) as i64;
+                self.carry %= FRAME_RATE;
+            }
+            Timing::Vip => self.budget += VIP_FRAME_CYCLES as i64,
+        }
+    }
+    fn spend(&mut self, cost: u32) {
+        self.budget -= cost as i64;
+    }
+    // give up the rest of this frame, e.g. to wait for the next interrupt
+    fn end_frame(&mut self) {
+        self.budget = self.budget.min(0);
+    }
+    // sleep until the current frame is due to end
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let frame_len = match self.pace {
+            Pace::Normal => self.frame_len,
+            Pace::Fast(n) => self.frame_len / n,
+            Pace::Slow(n) => self.frame_len * n,
+            Pace::Uncapped => {
+                self.deadline = now;
+                return;
+            }
+        };
+        if now < self.deadline {
+            sleep(self.deadline - now);
+        } else if now - self.deadline > frame_len * MAX_LAG {
+            self.deadline = now;
+        }
+        self.deadline += frame_len;
+    }
+}
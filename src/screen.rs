@@ -0,0 +1,247 @@
+// the window the display is shown in
+//
+// the window can be resized, and the display is letterboxed in it at 2:1
+// (MegaChip's screen at its own 4:3); the `[window]` section sets
+// `scaling` to `fit` (the default) or `integer` for whole-number scales
+// only
+//
+// F11 toggles fullscreen; minifb can't ask how big the monitor is or make a
+// real fullscreen window, so this is a borderless window at the top left of
+// `fullscreen_size` (default 1920x1080)
+//
+// the window's position and size are remembered in `chip8.window` between
+// runs
+
+use std::fs;
+
+use minifb::{ScaleMode, Window, WindowOptions};
+
+use crate::{
+    config::Section,
+    display::Display,
+    filter::Filter,
+    shader::{Area, Shader},
+    theme::Themes,
+};
+
+// the default window width, whatever the size of the buffer
+const WIDTH: usize = 1024;
+const GEOMETRY_PATH: &str = "chip8.window";
+
+// position and size
+type Geometry = (isize, isize, usize, usize);
+
+pub struct Screen {
+    pub window: Window,
+    pub themes: Themes,
+    filter: Filter,
+    pub shader: Shader,
+    integer: bool,
+    fullscreen_size: (usize, usize),
+    // where the window was before going fullscreen
+    windowed: Option<Geometry>,
+}
+
+impl Screen {
+    // `size` is the biggest buffer the display will have
+    pub fn new(
+        (width, height): (usize, usize),
+        themes: Themes,
+        filter: Filter,
+        shader: &Section,
+        section: &Section,
+    ) -> Result<Screen, String> {
+        let shader = Shader::from_config(shader)?;
+        let integer = match section.get("scaling").map(String::as_str) {
+            None | Some("fit") => false,
+            Some("integer") => true,
+            Some(val) => return Err(format!("Invalid scaling: {val}")),
+        };
+        let fullscreen_size = match section.get("fullscreen_size") {
+            None => (1920, 1080),
+            Some(val) => parse_size(val).ok_or(format!("Invalid fullscreen_size: {val}"))?,
+        };
+        let geometry = load_geometry().unwrap_or((0, 0, WIDTH, height * WIDTH / width));
+        Ok(Screen {
+            window: open(geometry, false),
+            themes,
+            filter,
+            shader,
+            integer,
+            fullscreen_size,
+            windowed: None,
+        })
+    }
+    pub fn toggle_fullscreen(&mut self) {
+        let geometry = match self.windowed.take() {
+            Some(geometry) => geometry,
+            None => {
+                self.windowed = Some(self.geometry());
+                let (width, height) = self.fullscreen_size;
+                (0, 0, width, height)
+            }
+        };
+        self.window = open(geometry, self.windowed.is_some());
+    }
+    fn geometry(&self) -> Geometry {
+        let (x, y) = self.window.get_position();
+        let (width, height) = self.window.get_size();
+        (x, y, width, height)
+    }
+    // remember the windowed geometry for next time
+    pub fn save_geometry(&self) {
+        let (x, y, width, height) = self.windowed.unwrap_or_else(|| self.geometry());
+        if let Err(e) = fs::write(GEOMETRY_PATH, format!("{x} {y} {width} {height}\n")) {
+            eprintln!("Unable to write {GEOMETRY_PATH}: {e}");
+        }
+    }
+    pub fn present(&mut self, disp: &Display) {
+        let size = disp.size();
+        let mut frame = disp.buffer();
+        // colour modes bring their own colours
+        let mono = disp.is_monochrome();
+        let mut lit: Vec<bool> = frame.iter().map(|&pixel| mono && pixel != 0).collect();
+        if mono {
+            self.filter.hold(&mut lit);
+            let palette = self.themes.palette();
+            for (pixel, &on) in frame.to_mut().iter_mut().zip(&lit) {
+                *pixel = palette[on as usize];
+            }
+        }
+        self.filter.fade(frame.to_mut(), &lit);
+        let window = self.window.get_size();
+        // minimised
+        if window.0 == 0 || window.1 == 0 {
+            self.window.update();
+            return;
+        }
+        // the VIP's pixels were twice as wide as tall at 64x64, so everything
+        // but MegaChip's screen is shown at 2:1
+        let shape = if disp.is_mega() { size } else { (2, 1) };
+        let area = letterbox(size, shape, window, self.integer);
+        let out = self.shader.run(&frame, size, window, area);
+        self.window
+            .update_with_buffer(out, window.0, window.1)
+            .unwrap();
+    }
+}
+
+fn open((x, y, width, height): Geometry, fullscreen: bool) -> Window {
+    let options = WindowOptions {
+        borderless: fullscreen,
+        title: !fullscreen,
+        resize: !fullscreen,
+        topmost: fullscreen,
+        // the buffer always matches the window, so minifb has nothing to do
+        scale_mode: ScaleMode::UpperLeft,
+        ..WindowOptions::default()
+    };
+    let mut window = Window::new("Chip-8", width, height, options).unwrap_or_else(|e| {
+        panic!("{e}");
+    });
+    window.set_position(x, y);
+    // pacing is the scheduler's job, minifb shouldn't add its own
+    window.set_target_fps(0);
+    window
+}
+
+// the biggest area of the window with the display's shape, centred; with
+// `integer`, every buffer pixel covers the same whole number of window
+// pixels
+fn letterbox(
+    (cols, rows): (usize, usize),
+    (shape_width, shape_height): (usize, usize),
+    (width, height): (usize, usize),
+    integer: bool,
+) -> Area {
+    let (mut area_width, mut area_height) = if width * shape_height > height * shape_width {
+        (height * shape_width / shape_height, height)
+    } else {
+        (width, width * shape_height / shape_width)
+    };
+    if integer {
+        // the smallest pixel with the right shape, which may not be square
+        let (pixel_width, pixel_height) = (shape_width * rows, shape_height * cols);
+        let common = gcd(pixel_width, pixel_height);
+        let (pixel_width, pixel_height) = (pixel_width / common, pixel_height / common);
+        let scale = (area_width / (cols * pixel_width)).min(area_height / (rows * pixel_height));
+        if scale > 0 {
+            (area_width, area_height) = (cols * pixel_width * scale, rows * pixel_height * scale);
+        }
+    }
+    Area {
+        x: (width - area_width) / 2,
+        y: (height - area_height) / 2,
+        width: area_width,
+        height: area_height,
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn load_geometry() -> Option<Geometry> {
+    let text = fs::read_to_string(GEOMETRY_PATH).ok()?;
+    let mut fields = text.split_whitespace();
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    let width = fields.next()?.parse().ok().filter(|&w| w > 0)?;
+    let height = fields.next()?.parse().ok().filter(|&h| h > 0)?;
+    Some((x, y, width, height))
+}
+
+// `<width>x<height>`
+fn parse_size(val: &str) -> Option<(usize, usize)> {
+    let (width, height) = val.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(area: Area) -> (usize, usize) {
+        (area.width, area.height)
+    }
+
+    #[test]
+    fn letterboxing() {
+        // 64x64 hires is as wide as lores
+        assert_eq!(
+            size(letterbox((64, 64), (2, 1), (1024, 768), false)),
+            (1024, 512)
+        );
+        assert_eq!(
+            size(letterbox((64, 32), (2, 1), (1024, 768), false)),
+            (1024, 512)
+        );
+        assert_eq!(
+            size(letterbox((256, 192), (256, 192), (1024, 512), false)),
+            (682, 512)
+        );
+        let area = letterbox((128, 64), (2, 1), (800, 800), false);
+        assert_eq!(
+            (area.x, area.y, area.width, area.height),
+            (0, 200, 800, 400)
+        );
+    }
+
+    #[test]
+    fn integer_scaling() {
+        // pixels twice as wide as tall
+        assert_eq!(
+            size(letterbox((64, 64), (2, 1), (1000, 600), true)),
+            (896, 448)
+        );
+        assert_eq!(
+            size(letterbox((64, 32), (2, 1), (1000, 600), true)),
+            (960, 480)
+        );
+        // too small to scale at all
+        assert_eq!(
+            size(letterbox((128, 64), (2, 1), (100, 50), true)),
+            (100, 50)
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// scaling the display up to the window on the cpu, with an optional effect
+//
+//   nearest    plain blocky pixels (the default)
+//   scanlines  the bottom row of each pixel darkened
+//   grid       the bottom row and right column of each pixel darkened
+//   crt        scanlines on a curved screen, with a little glow
+//
+// the `[shader]` section picks one with `name`, and F10 cycles through
+// them while running
+
+use crate::config::Section;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Effect {
+    Nearest,
+    Scanlines,
+    Grid,
+    Crt,
+}
+
+const EFFECTS: [(&str, Effect); 4] = [
+    ("nearest", Effect::Nearest),
+    ("scanlines", Effect::Scanlines),
+    ("grid", Effect::Grid),
+    ("crt", Effect::Crt),
+];
+// how much the crt screen bulges
+const CURVE: f32 = 0.08;
+
+// the part of the window the display goes in
+#[derive(Clone, Copy, PartialEq)]
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct Shader {
+    effect: usize,
+    out: Vec<u32>,
+    // for crt, which buffer pixel every pixel of the area shows, worked out
+    // again whenever the buffer or the area changes size
+    warp: Vec<Option<usize>>,
+    warp_for: (usize, usize, usize, usize),
+}
+
+impl Shader {
+    pub fn from_config(section: &Section) -> Result<Self, String> {
+        let name = section.get("name").map_or("nearest", String::as_str);
+        let effect = EFFECTS
+            .iter()
+            .position(|(effect, _)| *effect == name)
+            .ok_or(format!("Unknown shader: {name}"))?;
+        Ok(Shader {
+            effect,
+            out: Vec::new(),
+            warp: Vec::new(),
+            warp_for: (0, 0, 0, 0),
+        })
+    }
+    pub fn next(&mut self) {
+        self.effect = (self.effect + 1) % EFFECTS.len();
+    }
+    // scale a `cols` x `rows` frame into `area` of a `width` x `height`
+    // window, leaving the rest black
+    pub fn run(
+        &mut self,
+        frame: &[u32],
+        (cols, rows): (usize, usize),
+        (width, height): (usize, usize),
+        area: Area,
+    ) -> &[u32] {
+        let effect = EFFECTS[self.effect].1;
+        let warp_for = (cols, rows, area.width, area.height);
+        if effect == Effect::Crt && self.warp_for != warp_for {
+            self.warp = warp(cols, rows, area.width, area.height);
+            self.warp_for = warp_for;
+        }
+        self.out.clear();
+        self.out.resize(width * height, 0);
+        for y in 0..area.height {
+            // where in the buffer pixel this window pixel falls
+            let src_y = y * rows / area.height;
+            let last_row = (y + 1) * rows / area.height != src_y;
+            for x in 0..area.width {
+                let src_x = x * cols / area.width;
+                let last_col = (x + 1) * cols / area.width != src_x;
+                let pixel = frame[src_x + src_y * cols];
+                self.out[area.x + x + (area.y + y) * width] = match effect {
+                    Effect::Nearest => pixel,
+                    Effect::Scanlines if last_row => dim(pixel, 2),
+                    Effect::Grid if last_row || last_col => dim(pixel, 2),
+                    Effect::Scanlines | Effect::Grid => pixel,
+                    Effect::Crt => match self.warp[x + y * area.width] {
+                        Some(src) => {
+                            let glow = glow(frame, src, cols);
+                            if last_row { dim(glow, 3) } else { glow }
+                        }
+                        None => 0,
+                    },
+                };
+            }
+        }
+        &self.out
+    }
+}
+
+// scale each channel down to (n - 1)/n of its brightness
+fn dim(pixel: u32, n: u32) -> u32 {
+    let channel = |shift: u32| ((pixel >> shift & 0xFF) * (n - 1) / n) << shift;
+    channel(16) | channel(8) | channel(0)
+}
+
+// light from the neighbouring pixels on the same row bleeds in a little
+fn glow(frame: &[u32], src: usize, cols: usize) -> u32 {
+    let x = src % cols;
+    let left = if x > 0 { frame[src - 1] } else { 0 };
+    let right = if x + 1 < cols { frame[src + 1] } else { 0 };
+    let channel = |shift: u32| {
+        let side = (left >> shift & 0xFF) + (right >> shift & 0xFF);
+        ((frame[src] >> shift & 0xFF) + side / 8).min(0xFF) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+// barrel distortion: window pixels further from the centre look further
+// out, and the corners fall off the edge of the tube
+fn warp(cols: usize, rows: usize, width: usize, height: usize) -> Vec<Option<usize>> {
+    let mut map = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let u = x as f32 / width as f32 * 2.0 - 1.0;
+            let v = y as f32 / height as f32 * 2.0 - 1.0;
+            let bulge = 1.0 + CURVE * (u * u + v * v);
+            let (u, v) = ((u * bulge + 1.0) / 2.0, (v * bulge + 1.0) / 2.0);
+            map.push(
+                ((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v))
+                    .then(|| (u * cols as f32) as usize + (v * rows as f32) as usize * cols),
+            );
+        }
+    }
+    map
+}
@@ -0,0 +1,106 @@
+// runtime speed controls
+//
+//   Tab (held)       fast-forward
+//   F5               pause / resume
+//   F6               advance one frame while paused
+//   F7               slow motion: 1/2, 1/4, then back to normal
+//   PageUp/PageDown  more/fewer instructions per second
+//
+// the `[speed]` section sets `fast_forward` to a multiple (e.g. `4`) or
+// `uncapped`, which is the default
+
+use minifb::{Key, KeyRepeat, Window};
+
+use crate::{
+    config::Section,
+    scheduler::{FRAME_RATE, Pace, Scheduler, Timing},
+};
+
+pub struct Speed {
+    pub paused: bool,
+    fast_forward: Pace,
+    slow: u32,
+    // changing the ips mid-movie would throw the movie out of sync
+    ips_adjustable: bool,
+}
+
+impl Speed {
+    pub fn from_config(section: &Section, ips_adjustable: bool) -> Result<Self, String> {
+        let fast_forward = match section.get("fast_forward").map(String::as_str) {
+            None | Some("uncapped") => Pace::Uncapped,
+            Some(val) => val
+                .parse()
+                .ok()
+                .filter(|&n| n > 1)
+                .map(Pace::Fast)
+                .ok_or(format!("Invalid fast_forward: {val}"))?,
+        };
+        Ok(Speed {
+            paused: false,
+            fast_forward,
+            slow: 1,
+            ips_adjustable,
+        })
+    }
+    // handle the hotkeys and return whether a frame should be emulated now
+    pub fn poll(&mut self, window: &mut Window, sched: &mut Scheduler) -> bool {
+        let mut advance = false;
+        let mut changed = false;
+        if window.is_key_pressed(Key::F5, KeyRepeat::No) {
+            self.paused = !self.paused;
+            changed = true;
+        }
+        if window.is_key_pressed(Key::F6, KeyRepeat::Yes) && self.paused {
+            advance = true;
+        }
+        if window.is_key_pressed(Key::F7, KeyRepeat::No) {
+            self.slow = if self.slow >= 4 { 1 } else { self.slow * 2 };
+            changed = true;
+        }
+        if let Timing::Ips(ips) = sched.timing
+            && self.ips_adjustable
+        {
+            if window.is_key_pressed(Key::PageUp, KeyRepeat::Yes) {
+                sched.timing = Timing::Ips(ips + FRAME_RATE);
+                changed = true;
+            }
+            if window.is_key_pressed(Key::PageDown, KeyRepeat::Yes) && ips > FRAME_RATE {
+                sched.timing = Timing::Ips(ips - FRAME_RATE);
+                changed = true;
+            }
+        }
+        let pace = if self.paused {
+            Pace::Normal
+        } else if window.is_key_down(Key::Tab) {
+            self.fast_forward
+        } else if self.slow > 1 {
+            Pace::Slow(self.slow)
+        } else {
+            Pace::Normal
+        };
+        if pace != sched.pace {
+            sched.pace = pace;
+            changed = true;
+        }
+        if changed {
+            window.set_title(&self.title(sched));
+        }
+        !self.paused || advance
+    }
+    pub fn title(&self, sched: &Scheduler) -> String {
+        let mut title = match sched.timing {
+            Timing::Ips(ips) => format!("Chip-8 - {ips} IPS"),
+            Timing::Vip => "Chip-8 - VIP timing".to_string(),
+        };
+        match sched.pace {
+            Pace::Normal => (),
+            Pace::Fast(n) => title += &format!(" - {n}x"),
+            Pace::Slow(n) => title += &format!(" - 1/{n}x"),
+            Pace::Uncapped => title += " - fast-forward",
+        }
+        if self.paused {
+            title += " - paused";
+        }
+        title
+    }
+}
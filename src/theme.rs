@@ -0,0 +1,113 @@
+// colours for the monochrome displays
+//
+// the `[theme]` section picks a built-in theme with `name`, and can
+// override its colours as `#rrggbb`:
+//
+//   [theme]
+//   name = octo
+//   foreground = #ffcc00
+//
+// F9 cycles through the themes while running
+//
+// XO-CHIP's two bitplanes aren't drawn separately yet, so there are no
+// colours for the second plane or the overlap
+
+use crate::config::Section;
+
+// background and foreground
+pub type Palette = [u32; 2];
+
+const THEMES: [(&str, Palette); 5] = [
+    ("default", [0x000000, 0xFFFFFF]),
+    ("phosphor", [0x0A140A, 0x33FF66]),
+    ("lcd", [0x9BBC0F, 0x0F380F]),
+    ("octo", [0x996600, 0xFFCC00]),
+    // yellow on black, the usual high-contrast scheme
+    ("contrast", [0x000000, 0xFFFF00]),
+];
+const KEYS: [&str; 2] = ["background", "foreground"];
+
+pub struct Themes {
+    palettes: Vec<Palette>,
+    current: usize,
+}
+
+impl Themes {
+    pub fn from_config(section: &Section) -> Result<Self, String> {
+        let name = section.get("name").map_or("default", String::as_str);
+        let mut current = THEMES
+            .iter()
+            .position(|(theme, _)| *theme == name)
+            .ok_or(format!("Unknown theme: {name}"))?;
+        let mut palettes: Vec<Palette> = THEMES.iter().map(|(_, palette)| *palette).collect();
+        let mut custom = palettes[current];
+        for (key, val) in section {
+            if key == "name" {
+                continue;
+            }
+            // XO-CHIP's second plane isn't drawn separately
+            if key == "plane2" || key == "blend" {
+                return Err(format!(
+                    "Theme colour {key} isn't supported: bitplanes aren't implemented"
+                ));
+            }
+            let slot = KEYS
+                .iter()
+                .position(|k| k == key)
+                .ok_or(format!("Unknown theme colour: {key}"))?;
+            custom[slot] = parse_color(val).ok_or(format!("Invalid colour for {key}: {val}"))?;
+        }
+        // a customised theme goes at the end so cycling can still reach it
+        if custom != palettes[current] {
+            palettes.push(custom);
+            current = palettes.len() - 1;
+        }
+        Ok(Themes { palettes, current })
+    }
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.current]
+    }
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.palettes.len();
+    }
+}
+
+fn parse_color(val: &str) -> Option<u32> {
+    let hex = val
+        .strip_prefix('#')
+        .or(val.strip_prefix("0x"))
+        .unwrap_or(val);
+    u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn colour_override() {
+        let config = Config::parse(
+            "[theme]\nname = octo\nforeground = #ffcc00\nbackground = 0x102030\n",
+            "rom",
+        )
+        .unwrap();
+        let mut themes = Themes::from_config(&config.section("theme")).unwrap();
+        assert_eq!(themes.palette(), &[0x102030, 0xFFCC00]);
+        // the built-in themes are all still there
+        themes.next();
+        assert_eq!(themes.palette(), &THEMES[0].1);
+        // and no two are the same
+        for (i, (_, palette)) in THEMES.iter().enumerate() {
+            assert!(THEMES[i + 1..].iter().all(|(_, other)| other != palette));
+        }
+    }
+
+    #[test]
+    fn bad_colour() {
+        let config = Config::parse("[theme]\nforeground = #ffcc0\n", "rom").unwrap();
+        assert!(Themes::from_config(&config.section("theme")).is_err());
+        let config = Config::parse("[theme]\nplane2 = #ff6600\n", "rom").unwrap();
+        assert!(Themes::from_config(&config.section("theme")).is_err());
+    }
+}
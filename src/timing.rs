@@ -0,0 +1,56 @@
+// instruction costs on the COSMAC VIP
+//
+// the VIP's 1802 runs at 1.7609 MHz with 8 clocks per machine cycle, so a
+// 60 Hz frame is about 3668 machine cycles; the 1861 video chip takes 1024
+// of those for display DMA, leaving the rest for the interpreter
+//
+// costs are in machine cycles and follow published measurements of the
+// VIP interpreter; they're close but not exact, since page crossings and
+// the like add a few cycles here and there
+
+use crate::instruction::Instruction::{self, *};
+
+pub const VIP_FRAME_CYCLES: u32 = 3668 - 1024;
+// fetching and decoding any instruction
+const FETCH: u32 = 40;
+
+// `regs` are the registers before the instruction ran, `skipped` is
+// whether it skipped the next instruction
+pub fn vip_cycles(ins: &Instruction, regs: &[u8; 16], skipped: bool) -> u32 {
+    let skip = |taken: u32, not_taken: u32| if skipped { taken } else { not_taken };
+    FETCH
+        + match ins {
+            Clear => 24 + 3078,
+            Return => 10,
+            Jump(_) => 12,
+            Call(_) => 26,
+            SkipEqualConst(..) | SkipUnequalConst(..) => skip(14, 10),
+            SkipEqualReg(..) | SkipUnequalReg(..) => skip(18, 14),
+            SetConst(..) => 6,
+            AddConst(..) => 10,
+            SetReg(..) => 12,
+            Or(..) | And(..) | Xor(..) | AddReg(..) | SubRFromL(..) | RShift(..)
+            | SubLFromR(..) | LShift(..) => 44,
+            SetIndex(_) => 12,
+            JumpOffset(_) => 22,
+            Rand(..) => 36,
+            // sprites that aren't byte aligned get shifted a bit at a time
+            Draw(x, _, height) => {
+                let shift = (regs[*x] % 8) as u32;
+                26 + *height as u32 * (46 + 8 * shift)
+            }
+            SkipKey(_) | SkipNotKey(_) => skip(18, 14),
+            GetDelay(_) | SetDelay(_) | SetSound(_) => 10,
+            GetKey(_) => 10,
+            AddIndex(_) => 16,
+            Font(_) => 16,
+            // the digits are found by repeated subtraction
+            ConvertToDecimal(x) => {
+                let val = regs[*x];
+                84 + 16 * (val / 100 + val / 10 % 10 + val % 10) as u32
+            }
+            Store(x) | Load(x) => 14 + 14 * (*x as u32 + 1),
+            // SUPER-CHIP and XO-CHIP instructions don't exist on the VIP
+            _ => 0,
+        }
+}